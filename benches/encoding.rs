@@ -0,0 +1,73 @@
+//! Codes needed to send a 1 MiB (already compressed) file with each frame
+//! encoding, at a few fixed QR versions.
+//!
+//! Run with `cargo bench --bench encoding`.
+
+use qrcode::{EcLevel, QrCode, Version};
+use qrtransfer::protocol::{Encoding, Frame, Message};
+
+const FILE_SIZE: usize = 1 << 20;
+const ENCODINGS: [(&str, Encoding); 3] = [
+    ("base64", Encoding::Text),
+    ("base45", Encoding::Base45),
+    ("binary", Encoding::Binary),
+];
+
+fn random(len: usize) -> Vec<u8> {
+    let mut state = 0x2545f491u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn piece(encoding: Encoding, chunk: usize) -> Vec<u8> {
+    let msg = Message::piece(FILE_SIZE, random(chunk));
+    Frame::new(u32::MAX, msg).encode(encoding).unwrap()
+}
+
+fn max_chunk(encoding: Encoding, version: i16) -> usize {
+    let fits = |chunk| {
+        QrCode::with_version(piece(encoding, chunk), Version::Normal(version), EcLevel::M).is_ok()
+    };
+    let (mut low, mut high) = (0usize, 4096);
+    while low < high {
+        let mid = (low + high).div_ceil(2);
+        if fits(mid) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    low
+}
+
+fn main() {
+    println!("codes per 1 MiB file, EC level M");
+    print!("{:>8}", "version");
+    for (name, _) in ENCODINGS {
+        print!("{:>10}", name);
+    }
+    println!();
+    for version in [5, 10, 15, 20, 25, 30] {
+        print!("{:>8}", version);
+        for (_, encoding) in ENCODINGS {
+            let chunk = max_chunk(encoding, version);
+            print!("{:>10}", FILE_SIZE.div_ceil(chunk));
+        }
+        println!();
+    }
+
+    println!();
+    println!("QR version of a 100-byte piece");
+    for (name, encoding) in ENCODINGS {
+        let version = match QrCode::new(piece(encoding, 100)).unwrap().version() {
+            Version::Normal(v) | Version::Micro(v) => v,
+        };
+        println!("{:>8}{:>10}", name, version);
+    }
+}
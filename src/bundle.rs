@@ -0,0 +1,220 @@
+//! Several files sent as one transfer: a manifest with the path, size and hash
+//! of every file, followed by their contents. The metadata name of a bundle
+//! ends with `/`, which no file name can.
+
+use crate::receive::DecodeError;
+use crate::utils::hash;
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use std::path::{Component, Path};
+
+const MAGIC: &str = "QRTB:1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Relative, `/` separated.
+    pub path: String,
+    pub size: usize,
+    pub hash: String,
+}
+
+impl Entry {
+    fn line(&self) -> String {
+        format!(
+            "{},{},{}\n",
+            BASE64_STANDARD.encode(&self.path),
+            self.size,
+            self.hash
+        )
+    }
+
+    fn from_line(line: &str) -> Result<Self, DecodeError> {
+        let parts: Vec<&str> = line.split(',').collect();
+        let [path, size, hash] = parts.as_slice() else {
+            return Err(manifest_error(format!(
+                "Expected 3 parts, got {}",
+                parts.len()
+            )));
+        };
+        let path = BASE64_STANDARD
+            .decode(path)
+            .map_err(|e| DecodeError::BadBase64(e.to_string()))?;
+        let path = String::from_utf8(path).map_err(|e| manifest_error(e.to_string()))?;
+        if !is_relative(&path) {
+            return Err(manifest_error(format!("Unsafe path '{}'", path)));
+        }
+        Ok(Entry {
+            path,
+            size: size
+                .parse()
+                .map_err(|e| manifest_error(format!("Failed to parse size: {}", e)))?,
+            hash: hash.to_string(),
+        })
+    }
+}
+
+fn manifest_error(e: String) -> DecodeError {
+    DecodeError::Manifest(e)
+}
+
+/// Whether `path` stays inside the directory it is extracted to.
+fn is_relative(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+pub fn is_bundle(name: &str) -> bool {
+    name.ends_with('/')
+}
+
+/// The metadata name for `paths`: their common top directory, as when a folder
+/// was picked, or `files/`.
+pub fn name(paths: &[String]) -> String {
+    let top = |path: &String| path.split_once('/').map(|(top, _)| top.to_string());
+    let first = paths.first().and_then(top);
+    match first {
+        Some(first) if paths.iter().all(|path| top(path).as_ref() == Some(&first)) => {
+            format!("{}/", first)
+        }
+        _ => "files/".to_string(),
+    }
+}
+
+pub fn pack(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut output = format!("{}:{}\n", MAGIC, files.len()).into_bytes();
+    for (path, data) in files {
+        let entry = Entry {
+            path: path.clone(),
+            size: data.len(),
+            hash: hash(data),
+        };
+        output.extend(entry.line().into_bytes());
+    }
+    for (_, data) in files {
+        output.extend_from_slice(data);
+    }
+    output
+}
+
+fn read_line(data: &[u8]) -> Result<(&str, &[u8]), DecodeError> {
+    let end = data
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or(DecodeError::Truncated)?;
+    let line = std::str::from_utf8(&data[..end]).map_err(|e| manifest_error(e.to_string()))?;
+    Ok((line, &data[end + 1..]))
+}
+
+/// The files of a bundle, each checked against its hash in the manifest.
+pub fn unpack(data: &[u8]) -> Result<Vec<(Entry, Vec<u8>)>, DecodeError> {
+    let (header, mut rest) = read_line(data)?;
+    let count = header
+        .strip_prefix(MAGIC)
+        .and_then(|count| count.strip_prefix(':'))
+        .ok_or(manifest_error(format!("Unknown header '{}'", header)))?
+        .parse::<usize>()
+        .map_err(|e| manifest_error(format!("Failed to parse file count: {}", e)))?;
+
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let (line, next) = read_line(rest)?;
+        entries.push(Entry::from_line(line)?);
+        rest = next;
+    }
+
+    let mut files = Vec::new();
+    for entry in entries {
+        if rest.len() < entry.size {
+            return Err(DecodeError::Truncated);
+        }
+        let (data, next) = rest.split_at(entry.size);
+        rest = next;
+        let actual = hash(data);
+        if actual != entry.hash {
+            return Err(DecodeError::HashMismatch {
+                expected: entry.hash,
+                actual,
+            });
+        }
+        files.push((entry, data.to_vec()));
+    }
+    Ok(files)
+}
+
+/// The files as one tar archive, for saving the whole tree at once.
+pub fn tar(files: &[(Entry, Vec<u8>)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (entry, data) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, &entry.path, data.as_slice())
+            .expect("Failed writing to memory.");
+    }
+    builder.into_inner().expect("Failed writing to memory.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> Vec<(String, Vec<u8>)> {
+        vec![
+            ("photos/a.txt".to_string(), b"first, with a comma".to_vec()),
+            ("photos/sub/b.bin".to_string(), vec![0, 10, 255]),
+            ("photos/empty".to_string(), Vec::new()),
+        ]
+    }
+
+    #[test]
+    fn test_bundle() {
+        let files = files();
+        let paths = files
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(name(&paths), "photos/");
+        assert_eq!(name(&["a.txt".to_string()]), "files/");
+        assert!(is_bundle(&name(&paths)));
+
+        let packed = pack(&files);
+        let unpacked = unpack(&packed).unwrap();
+        assert_eq!(
+            unpacked
+                .iter()
+                .map(|(entry, data)| (entry.path.clone(), data.clone()))
+                .collect::<Vec<_>>(),
+            files
+        );
+        assert_eq!(unpacked[1].0.size, 3);
+
+        let archive = tar(&unpacked);
+        let mut archive = tar::Archive::new(archive.as_slice());
+        let paths = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["photos/a.txt", "photos/sub/b.bin", "photos/empty"]);
+
+        let mut corrupted = packed.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            unpack(&corrupted),
+            Err(DecodeError::HashMismatch { .. })
+        ));
+        assert_eq!(
+            unpack(&packed[..packed.len() - 1]),
+            Err(DecodeError::Truncated)
+        );
+
+        let unsafe_path = pack(&[("../escape".to_string(), Vec::new())]);
+        assert!(matches!(
+            unpack(&unsafe_path),
+            Err(DecodeError::Manifest(_))
+        ));
+    }
+}
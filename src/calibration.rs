@@ -0,0 +1,97 @@
+//! Capacity calibration between a sender and a receiver.
+//!
+//! Which QR version a camera reads from a given screen is hard to guess, so the
+//! sender can cycle through `REPEATS` test codes for each of `VERSIONS`, every
+//! one filled to the brim. The receiver counts which ones it decoded and shows
+//! the largest version it read completely; the user types that number back
+//! into the sender, which then sizes its pieces to fill that version.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// QR versions tested, from coarse to dense.
+pub const VERSIONS: [usize; 8] = [5, 10, 15, 20, 25, 30, 35, 40];
+/// Test codes per version; a version counts once all of them were decoded.
+pub const REPEATS: usize = 3;
+
+/// How many of the test codes of one version were decoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CalibrationLevel {
+    pub version: usize,
+    pub decoded: usize,
+    pub total: usize,
+}
+
+/// The test codes a receiver has decoded so far.
+#[derive(Default)]
+pub struct Calibration {
+    seen: BTreeMap<usize, (usize, BTreeSet<usize>)>,
+}
+
+impl Calibration {
+    /// Records test code `index` of `total` for `version`, returning whether it
+    /// was new. Codes of versions or counts no sender tests are stray or
+    /// forged, and ignored.
+    pub fn add(&mut self, version: usize, index: usize, total: usize) -> bool {
+        if !VERSIONS.contains(&version) || total != REPEATS || index >= total {
+            return false;
+        }
+        let (expected, indices) = self
+            .seen
+            .entry(version)
+            .or_insert_with(|| (total, BTreeSet::new()));
+        *expected = total;
+        indices.insert(index)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// The largest version of which every test code was decoded.
+    pub fn best(&self) -> Option<usize> {
+        self.seen
+            .iter()
+            .rev()
+            .find(|(_, (total, indices))| indices.len() >= *total)
+            .map(|(&version, _)| version)
+    }
+
+    pub fn levels(&self) -> Vec<CalibrationLevel> {
+        self.seen
+            .iter()
+            .map(|(&version, (total, indices))| CalibrationLevel {
+                version,
+                decoded: indices.len(),
+                total: *total,
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_calibration() {
+    let mut calibration = Calibration::default();
+    assert_eq!(calibration.best(), None);
+
+    for index in 0..REPEATS {
+        assert!(calibration.add(10, index, REPEATS));
+    }
+    assert!(!calibration.add(10, 0, REPEATS));
+    // only part of the denser codes came through
+    calibration.add(20, 1, REPEATS);
+    assert!(!calibration.add(20, REPEATS, REPEATS));
+    // a single code claiming to be all of an impossible version
+    assert!(!calibration.add(1000, 0, 1));
+    assert!(!calibration.add(40, 0, 1));
+
+    assert_eq!(calibration.best(), Some(10));
+    assert_eq!(
+        calibration.levels()[1],
+        CalibrationLevel {
+            version: 20,
+            decoded: 1,
+            total: REPEATS
+        }
+    );
+}
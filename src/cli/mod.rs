@@ -0,0 +1,208 @@
+//! The native command line, for machines with nothing but a terminal.
+
+mod receive;
+mod send;
+
+use crate::send::encoder::{parse_ec_level, EncoderOptions};
+use crate::sign;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use std::io::Write;
+
+const USAGE: &str = "\
+Usage: qrtransfer send <file or directory>... [options]
+       qrtransfer receive <image, video or directory>... [-o <path>] [--trust <key>]...
+                          [--max-size <bytes>]
+       qrtransfer keygen <secret key file>
+
+Send options:
+    --chunk <bytes>        compressed bytes per code (default 100, at least 16)
+    --version <1-40>       fill codes of this QR version instead
+    --ec <L|M|Q|H>         error correction level (default M)
+    --encoding <name>      binary, base45 or text (default binary)
+    --hash <name>          sha256, blake3 or sha1 (default sha256)
+    --compress <codec>     brotli[-0..11], zstd, deflate[-0..9] or none
+                           (default brotli-11), stored as is if no smaller
+    --fountain <percent>   append fountain codes
+    --parity <percent>     append parity codes
+    --no-quiet-zone        draw codes without the white border
+    --encrypt              encrypt with a passphrase, asked for or taken from
+                           $QRTRANSFER_PASSPHRASE
+    --sign <key file>      sign with a secret key from keygen
+
+Keys while sending:
+    space play/pause, left/right step, +/- speed, home metadata, q quit
+
+Receive options:
+    -o, --output <path>    file or directory to save to (default: sent name),
+                           several files are saved under this directory
+    --trust <public key>   only save transfers signed by this key, repeatable
+    --max-size <bytes>     refuse larger files (default 100 MiB)
+
+Encrypted transfers ask for the passphrase, or take it from $QRTRANSFER_PASSPHRASE.";
+
+/// Runs the command line and returns the process exit code.
+pub fn main() -> i32 {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("send") => send::run(&args[1..]),
+        Some("receive") => receive::run(&args[1..]),
+        Some("keygen") => keygen(&args[1..]),
+        Some("help" | "-h" | "--help") => {
+            println!("{}", USAGE);
+            return 0;
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("qrtransfer: {}", e);
+            1
+        }
+    }
+}
+
+/// Splits `args` into positional arguments and encoder options.
+fn parse_options(args: &[String]) -> Result<(Vec<String>, EncoderOptions), String> {
+    let mut positional = Vec::new();
+    let mut options = EncoderOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or(format!("{} needs a value", name))
+        };
+        let number = |name: &str, value: String| {
+            value
+                .parse::<usize>()
+                .map_err(|e| format!("Failed to parse {}: {}", name, e))
+        };
+        options = match arg.as_str() {
+            "--chunk" => options.chunk_size(number(arg, value(arg)?)?),
+            "--version" => {
+                let version = number(arg, value(arg)?)?;
+                if !(1..=40).contains(&version) {
+                    return Err(format!("QR version {} is not within 1-40", version));
+                }
+                options.version(Some(version as i16))
+            }
+            "--ec" => {
+                let level = value(arg)?;
+                let level = parse_ec_level(&level)
+                    .ok_or(format!("Unknown error correction level '{}'", level))?;
+                options.ec_level(level)
+            }
+            "--encoding" => options.encoding(value(arg)?.parse()?),
+            "--hash" => options.hash_algorithm(value(arg)?.parse()?),
+            "--compress" => options.codec(value(arg)?.parse()?),
+            "--fountain" => options.fountain(number(arg, value(arg)?)?),
+            "--parity" => options.parity(number(arg, value(arg)?)?),
+            "--no-quiet-zone" => options.quiet_zone(false),
+            "--encrypt" => options.passphrase(Some(passphrase("Passphrase: ")?)),
+            "--sign" => {
+                let path = value(arg)?;
+                let key = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+                options.signing_key(Some(sign::parse_signing_key(&key)?))
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+            _ => {
+                positional.push(arg.clone());
+                options
+            }
+        };
+    }
+    Ok((positional, options))
+}
+
+/// Writes a new secret key to the given file and prints its public key, for
+/// receivers to trust.
+fn keygen(args: &[String]) -> Result<(), String> {
+    let [path] = args else {
+        return Err("keygen needs the file to write the secret key to".to_string());
+    };
+    let key = sign::generate_key();
+    let mut file = std::fs::OpenOptions::new();
+    file.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+    file.open(path)
+        .and_then(|mut file| writeln!(file, "{}", sign::to_hex(&key.to_bytes())))
+        .map_err(|e| format!("{}: {}", path, e))?;
+    println!("{}", sign::to_hex(key.verifying_key().as_bytes()));
+    Ok(())
+}
+
+/// `$QRTRANSFER_PASSPHRASE`, or else typed in without echoing it.
+fn passphrase(prompt: &str) -> Result<String, String> {
+    if let Ok(passphrase) = std::env::var("QRTRANSFER_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    eprint!("{}", prompt);
+    terminal::enable_raw_mode().map_err(|e| format!("Terminal error: {}", e))?;
+    let typed = read_hidden();
+    let _ = terminal::disable_raw_mode();
+    eprintln!();
+    typed
+        .map_err(|e| format!("Terminal error: {}", e))?
+        .filter(|passphrase| !passphrase.is_empty())
+        .ok_or("No passphrase given".to_string())
+}
+
+fn read_hidden() -> std::io::Result<Option<String>> {
+    let mut typed = String::new();
+    loop {
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter => return Ok(Some(typed)),
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+            KeyCode::Char(c) => typed.push(c),
+            KeyCode::Backspace => {
+                typed.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_parse_options() {
+    use crate::protocol::Encoding;
+
+    let args: Vec<String> = [
+        "file.txt",
+        "--version",
+        "12",
+        "--ec",
+        "q",
+        "--encoding",
+        "base45",
+        "--hash",
+        "blake3",
+        "--compress",
+        "deflate-9",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let (positional, options) = parse_options(&args).unwrap();
+    assert_eq!(positional, vec!["file.txt"]);
+    assert_eq!(options.version, Some(12));
+    assert_eq!(options.ec_level, crate::send::encoder::EcLevel::Q);
+    assert_eq!(options.encoding, Encoding::Base45);
+    assert_eq!(options.hash_algorithm, crate::utils::HashAlgorithm::Blake3);
+    assert_eq!(options.codec, crate::compress::Codec::Deflate(9));
+
+    assert!(parse_options(&["--version".to_string(), "41".to_string()]).is_err());
+    assert!(parse_options(&["--chunk".to_string()]).is_err());
+}
@@ -0,0 +1,310 @@
+use super::passphrase;
+use crate::bundle::Entry;
+use crate::filename;
+use crate::protocol::FileInfo;
+use crate::receive::{video, Decoder};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+const EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "y4m", "mjpeg", "mjpg", "avi"];
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut output = None;
+    let mut trusted = Vec::new();
+    let mut max_size = None;
+    let mut inputs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output = Some(PathBuf::from(
+                    args.next().ok_or(format!("{} needs a value", arg))?,
+                ))
+            }
+            "--trust" => trusted.push(args.next().ok_or(format!("{} needs a value", arg))?),
+            "--max-size" => {
+                let bytes = args.next().ok_or(format!("{} needs a value", arg))?;
+                max_size = Some(
+                    bytes
+                        .parse::<usize>()
+                        .map_err(|e| format!("Failed to parse {}: {}", arg, e))?,
+                );
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+    if inputs.is_empty() {
+        return Err("receive needs at least one image, video or directory".to_string());
+    }
+
+    let mut decoder = Decoder::new();
+    if let Some(bytes) = max_size {
+        decoder.set_max_size(bytes);
+    }
+    for key in &trusted {
+        decoder.trust_key(key)?;
+    }
+    for path in files(&inputs)? {
+        let file = File::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let frames = video::frames(BufReader::new(file))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        if let Some(frames) = frames {
+            scan_video(&mut decoder, &path, frames)?;
+        } else {
+            let bytes = std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let image = image::load_from_memory(&bytes)
+                .map_err(|e| format!("{}: {}", path.display(), e))?
+                .into_luma8();
+            let new = decoder
+                .scan_gray(&image)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            println!(
+                "{}: {} new code(s). {}",
+                path.display(),
+                new.len(),
+                decoder.get_progress()
+            );
+        }
+        if decoder.is_finished() {
+            break;
+        }
+    }
+    if !decoder.is_finished() {
+        return Err(format!("Transfer incomplete. {}", decoder.get_progress()));
+    }
+
+    let mut finished = decoder.get_finished().map_err(|e| e.to_string())?;
+    // the sender's name may hold terminal escapes, only its safe form is shown
+    let name = finished.get_safe_name().map_err(|e| e.to_string())?;
+    let verification = finished.verification();
+    println!("{}", verification);
+    if !trusted.is_empty() && !verification.is_trusted() {
+        return Err(format!(
+            "Not saving {}, it is not signed by a trusted key",
+            name
+        ));
+    }
+    if finished.is_encrypted() {
+        let passphrase = passphrase(&format!("Passphrase for {}: ", name))?;
+        finished.unlock(&passphrase).map_err(|e| e.to_string())?;
+    }
+    if finished.is_bundle() {
+        let files = finished.files().map_err(|e| e.to_string())?;
+        return save_files(&files, &output.unwrap_or_default());
+    }
+    let data = finished.to_bytes().map_err(|e| e.to_string())?;
+    let path = match output {
+        Some(path) if path.is_dir() => path.join(&name),
+        Some(path) => path,
+        None => PathBuf::from(&name),
+    };
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()));
+    }
+    std::fs::write(&path, data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    restore_info(&path, finished.info()).map_err(|e| format!("{}: {}", path.display(), e))?;
+    println!("Saved {} to {}", name, path.display());
+    Ok(())
+}
+
+/// Gives the saved file the sender's modification time and permission bits,
+/// never setuid or the like.
+fn restore_info(path: &Path, info: &FileInfo) -> std::io::Result<()> {
+    if let Some(mtime) = info.mtime {
+        let file = std::fs::File::options().write(true).open(path)?;
+        file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime))?;
+    }
+    #[cfg(unix)]
+    if let Some(mode) = info.mode {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))?;
+    }
+    Ok(())
+}
+
+/// Recreates the sent tree in `dir`, writing nothing if any file exists.
+fn save_files(files: &[(Entry, Vec<u8>)], dir: &Path) -> Result<(), String> {
+    for (entry, _) in files {
+        let path = dir.join(filename::sanitize_path(&entry.path));
+        if path.exists() {
+            return Err(format!("{} already exists", path.display()));
+        }
+    }
+    for (entry, data) in files {
+        let path = dir.join(filename::sanitize_path(&entry.path));
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+        }
+        std::fs::write(&path, data).map_err(|e| format!("{}: {}", path.display(), e))?;
+        println!("Saved {}", path.display());
+    }
+    Ok(())
+}
+
+/// The given files, and the images and videos in the given directories in
+/// name order.
+fn files(inputs: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if !input.is_dir() {
+            files.push(input.clone());
+            continue;
+        }
+        let mut entries = std::fs::read_dir(input)
+            .map_err(|e| format!("{}: {}", input.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+            })
+            .collect::<Vec<PathBuf>>();
+        entries.sort();
+        files.extend(entries);
+    }
+    Ok(files)
+}
+
+/// Scans every frame, listing the codes each one contributed. Frames that
+/// don't decode are reported and skipped.
+fn scan_video(decoder: &mut Decoder, path: &Path, frames: video::Frames) -> Result<(), String> {
+    let mut count = 0;
+    for (i, frame) in frames.enumerate() {
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => {
+                eprintln!("{} frame {}: {}", path.display(), i + 1, e);
+                continue;
+            }
+        };
+        count += 1;
+        let new = decoder
+            .scan_gray(&frame)
+            .map_err(|e| format!("{} frame {}: {}", path.display(), i + 1, e))?;
+        if !new.is_empty() {
+            let labels = new
+                .iter()
+                .map(|msg| msg.label())
+                .collect::<Vec<String>>()
+                .join(", ");
+            println!("{} frame {}: {}", path.display(), i + 1, labels);
+        }
+        if decoder.is_finished() {
+            break;
+        }
+    }
+    println!(
+        "{}: {} frame(s). {}",
+        path.display(),
+        count,
+        decoder.get_progress()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_receive_images() {
+    use crate::send::encoder::{Encoder, EncoderOptions};
+    use image::{DynamicImage, Luma};
+    use qrcode::QrCode;
+
+    let dir = std::env::temp_dir().join(format!("qrtransfer-receive-{}", std::process::id()));
+    let shots = dir.join("shots");
+    std::fs::create_dir_all(&shots).unwrap();
+
+    let content = b"Photos of a screen, taken by any device.".to_vec();
+    let mut encoder = Encoder::new(
+        "../photos.txt".to_string(),
+        content.clone(),
+        EncoderOptions::default(),
+    );
+    encoder.set_file_info(FileInfo {
+        mtime: Some(1_600_000_000),
+        mode: Some(0o4640),
+        mime: None,
+    });
+    for (i, payload) in encoder.payloads().values().enumerate() {
+        let image = QrCode::new(payload).unwrap().render::<Luma<u8>>().build();
+        DynamicImage::ImageLuma8(image)
+            .save(shots.join(format!("{:03}.png", i)))
+            .unwrap();
+    }
+    std::fs::write(shots.join("notes.txt"), "not an image").unwrap();
+
+    let args = [
+        shots.display().to_string(),
+        "-o".to_string(),
+        dir.display().to_string(),
+    ];
+    // an unsigned transfer is refused once a sender key is required
+    let key = crate::sign::generate_key();
+    let trust = [
+        "--trust".to_string(),
+        crate::sign::to_hex(key.verifying_key().as_bytes()),
+    ];
+    assert!(run(&[&args[..], &trust[..]].concat()).is_err());
+    assert!(!dir.join("photos.txt").exists());
+
+    run(&args).unwrap();
+    assert_eq!(std::fs::read(dir.join("photos.txt")).unwrap(), content);
+    let saved = std::fs::metadata(dir.join("photos.txt")).unwrap();
+    assert_eq!(
+        saved.modified().unwrap(),
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000)
+    );
+    #[cfg(unix)]
+    assert_eq!(
+        std::os::unix::fs::PermissionsExt::mode(&saved.permissions()) & 0o7777,
+        0o640
+    );
+    // never overwrites
+    assert!(run(&args).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_receive_directory() {
+    use crate::send::encoder::{Encoder, EncoderOptions};
+    use image::{DynamicImage, Luma};
+    use qrcode::QrCode;
+
+    let dir = std::env::temp_dir().join(format!("qrtransfer-directory-{}", std::process::id()));
+    let sent = dir.join("sent");
+    std::fs::create_dir_all(sent.join("notes")).unwrap();
+    std::fs::write(sent.join("a.txt"), "first").unwrap();
+    std::fs::write(sent.join("notes/b.txt"), "second").unwrap();
+
+    let (name, data) = super::send::read(&[sent.display().to_string()]).unwrap();
+    assert_eq!(name, "sent/");
+    let encoder = Encoder::new(name, data, EncoderOptions::default());
+    let shots = dir.join("shots");
+    std::fs::create_dir_all(&shots).unwrap();
+    for (i, payload) in encoder.payloads().values().enumerate() {
+        let image = QrCode::new(payload).unwrap().render::<Luma<u8>>().build();
+        DynamicImage::ImageLuma8(image)
+            .save(shots.join(format!("{:03}.png", i)))
+            .unwrap();
+    }
+
+    let received = dir.join("received");
+    let args = [
+        shots.display().to_string(),
+        "-o".to_string(),
+        received.display().to_string(),
+    ];
+    run(&args).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(received.join("sent/a.txt")).unwrap(),
+        "first"
+    );
+    assert_eq!(
+        std::fs::read_to_string(received.join("sent/notes/b.txt")).unwrap(),
+        "second"
+    );
+    assert!(run(&args).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
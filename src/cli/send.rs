@@ -0,0 +1,335 @@
+use super::parse_options;
+use crate::bundle;
+use crate::compress::Codec;
+use crate::crypto;
+use crate::protocol::{mime_essence, FileInfo, MAX_FIELD};
+use crate::send::encoder::{Encoder, EncoderOptions, Packer};
+use crossterm::cursor::{Hide, MoveTo, MoveToNextLine, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{
+    self, disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen,
+    LeaveAlternateScreen,
+};
+use crossterm::{execute, queue};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// The playback speeds offered by `QrResPage`, 1x being 10 codes per second.
+const SPEEDS: [f32; 5] = [0.5, 1.0, 2.0, 5.0, 10.0];
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let (positional, options) = parse_options(args)?;
+    if positional.is_empty() {
+        return Err("send needs a file or directory".to_string());
+    }
+    let single = positional.len() == 1 && !Path::new(&positional[0]).is_dir();
+    let encoder = if single {
+        let path = Path::new(&positional[0]);
+        let size = std::fs::metadata(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?
+            .len();
+        check_size(
+            &file_name(path),
+            usize::try_from(size).unwrap_or(usize::MAX),
+        )?;
+        let mut encoder = pack(path, options)?;
+        encoder.set_file_info(file_info(path));
+        encoder
+    } else {
+        let (file_name, data) = read(&positional)?;
+        check_size(&file_name, data.len())?;
+        Encoder::new(file_name, data, options)
+    };
+    Player::new(encoder)
+        .play()
+        .map_err(|e| format!("Terminal error: {}", e))
+}
+
+fn check_size(file_name: &str, size: usize) -> Result<(), String> {
+    if size > MAX_FIELD - crypto::OVERHEAD {
+        return Err(format!(
+            "{} is too large, frames describe at most {} bytes",
+            file_name,
+            MAX_FIELD - crypto::OVERHEAD
+        ));
+    }
+    Ok(())
+}
+
+/// A single file, compressed as it is read so only its compressed copy is
+/// held. One that does not shrink is read again and sent as it is.
+fn pack(path: &Path, options: EncoderOptions) -> Result<Encoder, String> {
+    let mut packer = Packer::new(options.clone());
+    read_slices(path, |slice| packer.write(slice))?;
+    if let Some(encoder) = packer.finish(file_name(path)) {
+        return Ok(encoder);
+    }
+    let mut packer = Packer::new(options.codec(Codec::None));
+    read_slices(path, |slice| packer.write(slice))?;
+    Ok(packer.finish(file_name(path)).unwrap())
+}
+
+/// Bytes read at a time by `read_slices`.
+const READ_CHUNK: usize = 4 << 20;
+
+/// Hands the file to `write` a slice at a time.
+fn read_slices(path: &Path, mut write: impl FnMut(&[u8])) -> Result<(), String> {
+    let error = |e: io::Error| format!("{}: {}", path.display(), e);
+    let mut file = File::open(path).map_err(error)?;
+    let mut buffer = vec![0; READ_CHUNK];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => write(&buffer[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(error(e)),
+        }
+    }
+}
+
+/// The name and content of a bundle of all the files, directories keeping
+/// their structure. A single file is streamed by `pack` instead.
+pub fn read(inputs: &[String]) -> Result<(String, Vec<u8>), String> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            walk(path, &file_name(path), &mut files)?;
+        } else {
+            files.push((file_name(path), read_file(path)?));
+        }
+    }
+    let paths = files
+        .iter()
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<String>>();
+    Ok((bundle::name(&paths), bundle::pack(&files)))
+}
+
+fn file_info(path: &Path) -> FileInfo {
+    let metadata = std::fs::metadata(path).ok();
+    let mtime = metadata
+        .as_ref()
+        .and_then(|metadata| metadata.modified().ok())
+        .and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|mtime| mtime.as_secs());
+    #[cfg(unix)]
+    let mode = metadata
+        .map(|metadata| std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o777);
+    #[cfg(not(unix))]
+    let mode = None;
+    FileInfo {
+        mtime,
+        mode,
+        mime: mime_guess::from_path(path)
+            .first()
+            .and_then(|mime| mime_essence(mime.essence_str())),
+    }
+}
+
+fn walk(dir: &Path, prefix: &str, files: &mut Vec<(String, Vec<u8>)>) -> Result<(), String> {
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(|e| format!("{}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect::<Vec<_>>();
+    entries.sort();
+    for path in entries {
+        let name = format!("{}/{}", prefix, file_name(&path));
+        if path.is_dir() {
+            walk(&path, &name, files)?;
+        } else {
+            files.push((name, read_file(&path)?));
+        }
+    }
+    Ok(())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Restores the terminal however the player exits.
+struct Screen;
+
+impl Screen {
+    fn enter() -> io::Result<Screen> {
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = disable_raw_mode();
+    }
+}
+
+/// Draws each frame when it is shown, however many the file needs.
+struct Player {
+    encoder: Encoder,
+    total: usize,
+    /// Of playback, see `Encoder::playback`.
+    step: usize,
+    playing: bool,
+    speed: usize,
+}
+
+impl Player {
+    fn new(encoder: Encoder) -> Self {
+        Player {
+            total: encoder.frame_count(),
+            encoder,
+            step: 0,
+            playing: false,
+            speed: 1,
+        }
+    }
+
+    fn title(&self) -> String {
+        let total = self.total;
+        let index = self.encoder.playback(self.step);
+        let title = if index == 0 {
+            "* Scan this METADATA before playing".to_string()
+        } else if index >= total {
+            format!("{}, fountain codes until stopped", index)
+        } else {
+            format!("{} / {}", index, total - 1)
+        };
+        format!(
+            "{}  {}  {}x",
+            title,
+            if self.playing { "playing" } else { "paused" },
+            SPEEDS[self.speed]
+        )
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        let frame = self.encoder.terminal(self.encoder.playback(self.step));
+        queue!(out, MoveTo(0, 0), Clear(ClearType::All))?;
+
+        let (columns, rows) = terminal::size()?;
+        let width = frame.lines().map(|l| l.chars().count()).max().unwrap_or(0);
+        let height = frame.lines().count();
+        if width > columns as usize || height + 2 > rows as usize {
+            queue!(
+                out,
+                Print(format!(
+                    "The code needs {}x{} characters, the terminal has {}x{}.",
+                    width,
+                    height + 2,
+                    columns,
+                    rows
+                )),
+                MoveToNextLine(1),
+                Print("Enlarge the terminal, or pick a lower --version or --chunk."),
+                MoveToNextLine(1),
+            )?;
+        } else {
+            for line in frame.lines() {
+                queue!(out, Print(line), MoveToNextLine(1))?;
+            }
+        }
+        queue!(
+            out,
+            Print(self.title()),
+            MoveToNextLine(1),
+            Print("space play/pause, left/right step, +/- speed, home metadata, q quit"),
+        )?;
+        out.flush()
+    }
+
+    fn step(&mut self, forward: bool) {
+        let total = self.total;
+        self.step = match (forward, self.encoder.is_endless()) {
+            (true, true) => self.step + 1,
+            (true, false) => (self.step + 1) % total,
+            (false, _) if self.step == 0 => total - 1,
+            (false, _) => self.step - 1,
+        };
+    }
+
+    fn play(mut self) -> io::Result<()> {
+        if self.total == 0 {
+            return Ok(());
+        }
+        let _screen = Screen::enter()?;
+        let mut out = io::stdout();
+        loop {
+            self.draw(&mut out)?;
+            let interval = Duration::from_secs_f32(0.1 / SPEEDS[self.speed]);
+            if !event::poll(if self.playing {
+                interval
+            } else {
+                Duration::from_secs(3600)
+            })? {
+                if self.playing {
+                    self.step(true);
+                }
+                continue;
+            }
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(())
+                }
+                KeyCode::Char(' ') => self.playing = !self.playing,
+                KeyCode::Left => self.step(false),
+                KeyCode::Right => self.step(true),
+                KeyCode::Char('+' | '=') | KeyCode::Up => {
+                    self.speed = (self.speed + 1).min(SPEEDS.len() - 1)
+                }
+                KeyCode::Char('-') | KeyCode::Down => self.speed = self.speed.saturating_sub(1),
+                KeyCode::Home => self.step = 0,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn test_pack() {
+    let dir = std::env::temp_dir().join(format!("qrtransfer-pack-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut state = 0x2545f491u32;
+    let noise = (0..READ_CHUNK + 1000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect::<Vec<u8>>();
+    for (name, content) in [
+        ("text.txt", vec![b'a'; READ_CHUNK + 1000]),
+        ("noise.bin", noise),
+    ] {
+        let path = dir.join(name);
+        std::fs::write(&path, &content).unwrap();
+        let options = EncoderOptions::default().codec(Codec::Zstd);
+        let packed = pack(&path, options.clone()).unwrap().metadata();
+        let whole = Encoder::new(name.to_string(), content, options).metadata();
+        assert_eq!(packed.size, whole.size);
+        assert_eq!(packed.codec, whole.codec);
+        assert_eq!(packed.codec == Codec::None, name == "noise.bin");
+        assert_eq!(packed.name, whole.name);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
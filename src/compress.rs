@@ -1,21 +1,233 @@
-use std::io::Write;
+use crate::receive::DecodeError;
+use miniz_oxide::deflate::core::{
+    compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+};
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
 
-pub fn compress(input: Vec<u8>) -> Vec<u8> {
-    let mut output = Vec::new();
-    {
-        let mut writer = brotli::CompressorWriter::new(&mut output, 4096, 11, 22);
-        writer.write_all(&input).expect("Failed compressing.");
+/// How the file is compressed before it is cut into pieces, named in the
+/// metadata. Metadata naming none is from an older sender and means Brotli.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// Stored as is, for data that doesn't shrink.
+    None,
+    /// Brotli at quality 0-11.
+    Brotli(u32),
+    /// Zstandard at its fastest level, the only one implemented in pure Rust.
+    Zstd,
+    /// Deflate at level 0-9.
+    Deflate(u32),
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::Brotli(11)
+    }
+}
+
+impl Codec {
+    pub fn compress(&self, input: &[u8]) -> Vec<u8> {
+        let mut compressor = self.compressor();
+        compressor.write(input);
+        compressor.finish()
+    }
+
+    /// For data handed over a slice at a time, only the output is kept.
+    pub fn compressor(&self) -> Compressor {
+        match self {
+            Codec::None => Compressor::None(Vec::new()),
+            Codec::Brotli(quality) => Compressor::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                *quality,
+                22,
+            ))),
+            Codec::Zstd => Compressor::Zstd(Vec::new()),
+            Codec::Deflate(level) => Compressor::Deflate(
+                Box::new(CompressorOxide::new(create_comp_flags_from_zip_params(
+                    *level as i32,
+                    0,
+                    0,
+                ))),
+                Vec::new(),
+            ),
+        }
+    }
+
+    /// Fails with `TooLarge` as soon as the output grows past `limit` bytes,
+    /// so a few codes can't expand into more memory than the receiver allows.
+    pub fn decompress(&self, input: Vec<u8>, limit: usize) -> Result<Vec<u8>, DecodeError> {
+        let too_large = DecodeError::TooLarge { limit };
+        let mut output = Vec::new();
+        match self {
+            Codec::None if input.len() > limit => return Err(too_large),
+            Codec::None => return Ok(input),
+            Codec::Brotli(_) => {
+                let mut writer = brotli::DecompressorWriter::new(
+                    Limited {
+                        output: &mut output,
+                        limit,
+                    },
+                    4096,
+                );
+                writer.write_all(&input).map_err(|e| match e.kind() {
+                    std::io::ErrorKind::FileTooLarge => too_large.clone(),
+                    _ => DecodeError::Decompression(e.to_string()),
+                })?;
+                // all the input was valid, the stream just did not end
+                writer.close().map_err(|e| match e.kind() {
+                    std::io::ErrorKind::FileTooLarge => too_large.clone(),
+                    _ => DecodeError::Truncated,
+                })?;
+            }
+            Codec::Zstd => {
+                // one frame per slice the sender compressed
+                let mut input = input.as_slice();
+                while !input.is_empty() {
+                    ruzstd::decoding::StreamingDecoder::new(&mut input)
+                        .map_err(|e| DecodeError::Decompression(e.to_string()))?
+                        .take((limit - output.len()) as u64 + 1)
+                        .read_to_end(&mut output)
+                        .map_err(|e| match e.kind() {
+                            std::io::ErrorKind::UnexpectedEof => DecodeError::Truncated,
+                            _ => DecodeError::Decompression(e.to_string()),
+                        })?;
+                    if output.len() > limit {
+                        return Err(too_large);
+                    }
+                }
+            }
+            Codec::Deflate(_) => {
+                output = miniz_oxide::inflate::decompress_to_vec_with_limit(&input, limit)
+                    .map_err(|e| match e.status {
+                        miniz_oxide::inflate::TINFLStatus::HasMoreOutput => too_large.clone(),
+                        miniz_oxide::inflate::TINFLStatus::FailedCannotMakeProgress => {
+                            DecodeError::Truncated
+                        }
+                        _ => DecodeError::Decompression(e.to_string()),
+                    })?;
+            }
+        }
+        Ok(output)
     }
-    output
 }
 
-pub fn decompress(input: Vec<u8>) -> Vec<u8> {
-    let mut output = Vec::new();
-    {
-        let mut writer = brotli::DecompressorWriter::new(&mut output, 4096);
-        writer.write_all(&input).expect("Failed decompressing.");
+/// Compresses data as it comes, see `Codec::compressor`.
+pub enum Compressor {
+    None(Vec<u8>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    /// Every slice becomes a frame of its own, ruzstd only compresses whole
+    /// inputs.
+    Zstd(Vec<u8>),
+    Deflate(Box<CompressorOxide>, Vec<u8>),
+}
+
+impl Compressor {
+    pub fn write(&mut self, input: &[u8]) {
+        match self {
+            Compressor::None(output) => output.extend_from_slice(input),
+            Compressor::Brotli(writer) => writer.write_all(input).expect("Failed compressing."),
+            Compressor::Zstd(output) => output.extend(ruzstd::encoding::compress_to_vec(
+                input,
+                ruzstd::encoding::CompressionLevel::Fastest,
+            )),
+            Compressor::Deflate(compressor, output) => {
+                deflate(compressor, output, input, TDEFLFlush::None)
+            }
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        match self {
+            Compressor::None(output) | Compressor::Zstd(output) => output,
+            Compressor::Brotli(writer) => writer.into_inner(),
+            Compressor::Deflate(mut compressor, mut output) => {
+                deflate(&mut compressor, &mut output, &[], TDEFLFlush::Finish);
+                output
+            }
+        }
+    }
+}
+
+fn deflate(
+    compressor: &mut CompressorOxide,
+    output: &mut Vec<u8>,
+    input: &[u8],
+    flush: TDEFLFlush,
+) {
+    let (status, _) = compress_to_output(compressor, input, flush, |out| {
+        output.extend_from_slice(out);
+        true
+    });
+    assert!(
+        matches!(status, TDEFLStatus::Okay | TDEFLStatus::Done),
+        "Failed compressing."
+    );
+}
+
+/// Collects output, refusing writes past `limit` bytes.
+struct Limited<'a> {
+    output: &'a mut Vec<u8>,
+    limit: usize,
+}
+
+impl Write for Limited<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.output.len() + buf.len() > self.limit {
+            return Err(std::io::ErrorKind::FileTooLarge.into());
+        }
+        self.output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::None => write!(f, "none"),
+            Codec::Brotli(quality) => write!(f, "brotli-{}", quality),
+            Codec::Zstd => write!(f, "zstd"),
+            Codec::Deflate(level) => write!(f, "deflate-{}", level),
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    /// `none`, `zstd`, or `brotli`/`deflate` with an optional `-<level>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, level) = match s.split_once('-') {
+            Some((name, level)) => {
+                let level = level
+                    .parse::<u32>()
+                    .map_err(|e| format!("Failed to parse compression level: {}", e))?;
+                (name, Some(level))
+            }
+            None => (s, None),
+        };
+        let codec = match (name, level) {
+            ("none", None) => Codec::None,
+            ("zstd", None) => Codec::Zstd,
+            ("brotli", level) => Codec::Brotli(level.unwrap_or(11)),
+            ("deflate", level) => Codec::Deflate(level.unwrap_or(6)),
+            _ => return Err(format!("Unknown compression '{}'", s)),
+        };
+        match codec {
+            Codec::Brotli(quality) if quality > 11 => {
+                Err(format!("Brotli quality {} is not within 0-11", quality))
+            }
+            Codec::Deflate(level) if level > 9 => {
+                Err(format!("Deflate level {} is not within 0-9", level))
+            }
+            _ => Ok(codec),
+        }
     }
-    output
 }
 
 #[test]
@@ -30,7 +242,62 @@ fn test_compress() {
         104, 40, 114, 39, 37, 49, 39, 41, 41, 41, 34,
     ];
 
-    let compressed = compress(data.clone());
-    let decompressed = decompress(compressed);
-    assert_eq!(data, decompressed)
+    for codec in [
+        Codec::default(),
+        Codec::Brotli(1),
+        Codec::Zstd,
+        Codec::Deflate(9),
+        Codec::None,
+    ] {
+        let compressed = codec.compress(&data);
+        assert_eq!(
+            codec.decompress(compressed.clone(), data.len()).unwrap(),
+            data
+        );
+        assert_eq!(codec.to_string().parse::<Codec>(), Ok(codec));
+        if codec != Codec::None {
+            assert!(compressed.len() < data.len());
+            let truncated = compressed[..compressed.len() - 4].to_vec();
+            assert!(codec.decompress(truncated, data.len()).is_err());
+        }
+    }
+
+    let compressed = Codec::default().compress(&data);
+    let truncated = compressed[..compressed.len() - 4].to_vec();
+    assert_eq!(
+        Codec::default().decompress(truncated, data.len()),
+        Err(DecodeError::Truncated)
+    );
+    // a bomb stops at the limit
+    let zeros = vec![0; 100_000];
+    for codec in [
+        Codec::default(),
+        Codec::Zstd,
+        Codec::Deflate(9),
+        Codec::None,
+    ] {
+        assert_eq!(
+            codec.decompress(codec.compress(&zeros), 1_000),
+            Err(DecodeError::TooLarge { limit: 1_000 })
+        );
+    }
+
+    // fed a slice at a time, as a sender reading a large file does
+    for codec in [
+        Codec::default(),
+        Codec::Zstd,
+        Codec::Deflate(6),
+        Codec::None,
+    ] {
+        let mut compressor = codec.compressor();
+        for slice in data.chunks(10) {
+            compressor.write(slice);
+        }
+        let compressed = compressor.finish();
+        assert_eq!(codec.decompress(compressed, data.len()), Ok(data.clone()));
+    }
+
+    assert_eq!("brotli".parse::<Codec>(), Ok(Codec::Brotli(11)));
+    assert!("brotli-12".parse::<Codec>().is_err());
+    assert!("lzma".parse::<Codec>().is_err());
 }
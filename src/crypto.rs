@@ -0,0 +1,71 @@
+//! Optional passphrase encryption of the compressed file, for codes shown where
+//! others can photograph them. The key is derived with scrypt and the data
+//! sealed with ChaCha20-Poly1305, so a wrong passphrase or altered data fails
+//! to open rather than producing garbage.
+
+use crate::receive::DecodeError;
+use chacha20poly1305::aead::{Aead, AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+/// Named in the metadata of encrypted transfers.
+pub const SCHEME: &str = "scrypt-chacha20poly1305";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// Bytes encryption adds to the data: salt, nonce and the Poly1305 tag.
+pub const OVERHEAD: usize = SALT_LEN + NONCE_LEN + 16;
+/// scrypt cost, 2^15 iterations take well under a second on a phone.
+const LOG_N: u8 = 15;
+
+fn key(passphrase: &str, salt: &[u8]) -> Key {
+    let params = scrypt::Params::new(LOG_N, 8, 1, 32).unwrap();
+    let mut key = Key::default();
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key).unwrap();
+    key
+}
+
+/// A random salt and nonce followed by the sealed data. The data is sealed
+/// where it lies, a large file is never held twice.
+pub fn encrypt(mut data: Vec<u8>, passphrase: &str) -> Vec<u8> {
+    let mut header = [0; SALT_LEN + NONCE_LEN];
+    getrandom::getrandom(&mut header).expect("No source of randomness.");
+    let (salt, nonce) = header.split_at(SALT_LEN);
+    let tag = ChaCha20Poly1305::new(&key(passphrase, salt))
+        .encrypt_in_place_detached(Nonce::from_slice(nonce), b"", &mut data)
+        .expect("Failed encrypting.");
+    data.reserve_exact(OVERHEAD);
+    data.extend_from_slice(&tag);
+    data.splice(0..0, header);
+    data
+}
+
+pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, DecodeError> {
+    if data.len() < SALT_LEN + NONCE_LEN {
+        return Err(DecodeError::Truncated);
+    }
+    let (salt, rest) = data.split_at(SALT_LEN);
+    let (nonce, sealed) = rest.split_at(NONCE_LEN);
+    ChaCha20Poly1305::new(&key(passphrase, salt))
+        .decrypt(Nonce::from_slice(nonce), sealed)
+        .map_err(|_| DecodeError::Decryption)
+}
+
+#[test]
+fn test_crypto() {
+    let data = b"Seen by everyone in the room, read by one.".to_vec();
+    let sealed = encrypt(data.clone(), "correct horse");
+    assert_eq!(sealed.len(), data.len() + OVERHEAD);
+    assert_ne!(sealed, encrypt(data.clone(), "correct horse"));
+    assert_eq!(decrypt(&sealed, "correct horse").unwrap(), data);
+    assert_eq!(
+        decrypt(&sealed, "battery staple"),
+        Err(DecodeError::Decryption)
+    );
+
+    let mut altered = sealed.clone();
+    altered[SALT_LEN + NONCE_LEN] ^= 1;
+    assert_eq!(
+        decrypt(&altered, "correct horse"),
+        Err(DecodeError::Decryption)
+    );
+}
@@ -0,0 +1,127 @@
+//! File names chosen by the sender, made safe to save under on any system.
+//! Whatever arrives in the metadata is only ever shown or used this way.
+
+/// Longest name most file systems accept, in bytes.
+const MAX_LEN: usize = 255;
+/// Longer extensions are not worth keeping when a name is cut short.
+const MAX_EXTENSION_LEN: usize = 16;
+/// Names Windows reserves for devices, with or without an extension.
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Invisible formatting characters (Unicode category Cf), among them the
+/// bidirectional overrides and marks that make `invoice\u{202E}fdp.exe` read
+/// as `invoiceexe.pdf`.
+fn is_format(c: char) -> bool {
+    matches!(
+        c,
+        '\u{ad}'
+            | '\u{600}'..='\u{605}'
+            | '\u{61c}'
+            | '\u{6dd}'
+            | '\u{70f}'
+            | '\u{890}'..='\u{891}'
+            | '\u{8e2}'
+            | '\u{180e}'
+            | '\u{200b}'..='\u{200f}'
+            | '\u{202a}'..='\u{202e}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{206f}'
+            | '\u{feff}'
+            | '\u{fff9}'..='\u{fffb}'
+            | '\u{110bd}'
+            | '\u{110cd}'
+            | '\u{13430}'..='\u{1343f}'
+            | '\u{1bca0}'..='\u{1bca3}'
+            | '\u{1d173}'..='\u{1d17a}'
+            | '\u{e0001}'
+            | '\u{e0020}'..='\u{e007f}'
+    )
+}
+
+/// A single file name: the last component of a path, without control or
+/// formatting characters or characters Windows refuses, no leading dots, no device names
+/// and at most 255 bytes, cut short before the extension. Never empty.
+pub fn sanitize(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name = name
+        .chars()
+        .filter(|&c| !c.is_control() && !is_format(c))
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c => c,
+        })
+        .collect::<String>();
+    // no hidden files or `..`, and Windows drops trailing dots and spaces
+    let name = name
+        .trim_start_matches(['.', ' '])
+        .trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        return "received".to_string();
+    }
+
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension))
+            if !stem.is_empty()
+                && extension.len() <= MAX_EXTENSION_LEN
+                && extension.chars().all(|c| c.is_ascii_alphanumeric()) =>
+        {
+            (stem, Some(extension))
+        }
+        _ => (name, None),
+    };
+    let mut stem = stem.to_string();
+    let device = stem.split('.').next().unwrap_or_default();
+    if RESERVED.contains(&device.to_ascii_uppercase().as_str()) {
+        stem.insert(0, '_');
+    }
+
+    let room = MAX_LEN - extension.map_or(0, |extension| extension.len() + 1);
+    while stem.len() > room {
+        stem.pop();
+    }
+    match extension {
+        Some(extension) => format!("{}.{}", stem.trim_end_matches(['.', ' ']), extension),
+        None => stem,
+    }
+}
+
+/// A relative path from a multi-file transfer, each component sanitized.
+pub fn sanitize_path(path: &str) -> String {
+    path.split('/')
+        .filter(|component| !component.is_empty())
+        .map(sanitize)
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+#[test]
+fn test_sanitize() {
+    assert_eq!(sanitize("report.pdf"), "report.pdf");
+    assert_eq!(sanitize("../../etc/passwd"), "passwd");
+    assert_eq!(sanitize("C:\\Windows\\win.ini"), "win.ini");
+    assert_eq!(sanitize("C:win.ini"), "C_win.ini");
+    assert_eq!(sanitize("photos/"), "received");
+    assert_eq!(sanitize("bell\x07\n.txt"), "bell.txt");
+    assert_eq!(sanitize("invoice\u{202E}fdp.exe"), "invoicefdp.exe");
+    assert_eq!(sanitize("\u{200E}a\u{2066}b\u{2069}\u{200B}.txt"), "ab.txt");
+    assert_eq!(sanitize(".bashrc"), "bashrc");
+    assert_eq!(sanitize("notes. . "), "notes");
+    assert_eq!(sanitize(".."), "received");
+    assert_eq!(sanitize(""), "received");
+    assert_eq!(sanitize("con"), "_con");
+    assert_eq!(sanitize("Nul.tar.gz"), "_Nul.tar.gz");
+    assert_eq!(sanitize("console.log"), "console.log");
+    assert_eq!(sanitize("what?.txt"), "what_.txt");
+
+    let long = format!("{}.jpeg", "é".repeat(200));
+    let short = sanitize(&long);
+    assert!(short.len() <= MAX_LEN);
+    assert!(short.ends_with("é.jpeg"));
+    let long = format!("{}.not an extension", "a".repeat(300));
+    assert_eq!(sanitize(&long), "a".repeat(MAX_LEN));
+
+    assert_eq!(sanitize_path("photos/aux/1.jpg"), "photos/_aux/1.jpg");
+}
@@ -0,0 +1,259 @@
+//! LT (Luby transform) fountain code over fixed-size blocks.
+//!
+//! The sender splits the data into `k` blocks and can emit an endless stream of
+//! symbols, each the XOR of `degree` blocks chosen pseudo-randomly from its seed.
+//! The degree is carried alongside the seed, so the receiver only needs the
+//! integer PRNG below to find a symbol's neighbours and never has to agree with
+//! the sender on floating point details of the degree distribution.
+
+use std::collections::BTreeSet;
+
+const C: f64 = 0.1;
+const DELTA: f64 = 0.5;
+
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    // splitmix64
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Robust soliton degree distribution for `k` blocks.
+#[derive(Clone)]
+pub struct Distribution {
+    cdf: Vec<f64>,
+}
+
+impl Distribution {
+    pub fn new(k: usize) -> Self {
+        let kf = k as f64;
+        let r = C * (kf / DELTA).ln() * kf.sqrt();
+        let spike = if r > 0.0 {
+            ((kf / r).floor() as usize).clamp(1, k)
+        } else {
+            k
+        };
+
+        let mut weights = Vec::with_capacity(k);
+        for d in 1..=k {
+            let df = d as f64;
+            let rho = if d == 1 {
+                1.0 / kf
+            } else {
+                1.0 / (df * (df - 1.0))
+            };
+            let tau = if d < spike {
+                r / (df * kf)
+            } else if d == spike {
+                r * (r / DELTA).ln().max(0.0) / kf
+            } else {
+                0.0
+            };
+            weights.push(rho + tau);
+        }
+
+        let total: f64 = weights.iter().sum();
+        let mut acc = 0.0;
+        let cdf = weights
+            .into_iter()
+            .map(|w| {
+                acc += w / total;
+                acc
+            })
+            .collect();
+        Distribution { cdf }
+    }
+
+    /// A degree in `1..=k`, or 0 when there are no blocks.
+    pub fn sample(&self, seed: u32) -> usize {
+        if self.cdf.is_empty() {
+            return 0;
+        }
+        let mut rng = Rng::new(!(seed as u64));
+        let u = (rng.next() >> 11) as f64 / (1u64 << 53) as f64;
+        self.cdf
+            .iter()
+            .position(|&p| u < p)
+            .unwrap_or(self.cdf.len() - 1)
+            + 1
+    }
+}
+
+/// The block indices XORed into the symbol with the given seed and degree.
+pub fn neighbours(seed: u32, degree: usize, k: usize) -> Vec<usize> {
+    let degree = degree.min(k);
+    let mut rng = Rng::new(seed as u64);
+    let mut picked = BTreeSet::new();
+    while picked.len() < degree {
+        picked.insert(rng.below(k));
+    }
+    picked.into_iter().collect()
+}
+
+fn xor_into(target: &mut [u8], other: &[u8]) {
+    for (t, o) in target.iter_mut().zip(other) {
+        *t ^= o;
+    }
+}
+
+/// XOR the neighbouring blocks of the `k` given by `block` into one symbol,
+/// zero-padding the short last block.
+pub fn encode<'a>(
+    k: usize,
+    block: impl Fn(usize) -> &'a [u8],
+    block_size: usize,
+    seed: u32,
+    degree: usize,
+) -> Vec<u8> {
+    let mut symbol = vec![0; block_size];
+    for i in neighbours(seed, degree, k) {
+        xor_into(&mut symbol, block(i));
+    }
+    symbol
+}
+
+/// Peeling decoder recovering blocks from any mix of source blocks and symbols.
+pub struct FountainDecoder {
+    size: usize,
+    block_size: usize,
+    blocks: Vec<Option<Vec<u8>>>,
+    pending: Vec<(Vec<usize>, Vec<u8>)>,
+}
+
+impl FountainDecoder {
+    pub fn new(size: usize, block_size: usize) -> Self {
+        let k = size.div_ceil(block_size);
+        FountainDecoder {
+            size,
+            block_size,
+            blocks: vec![None; k],
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn k(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Number of received symbols that could not be resolved yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.blocks.iter().all(Option::is_some)
+    }
+
+    /// The recovered block, trimmed to its real length.
+    pub fn block(&self, index: usize) -> Option<&[u8]> {
+        let end = ((index + 1) * self.block_size).min(self.size);
+        let len = end.saturating_sub(index * self.block_size);
+        self.blocks.get(index)?.as_ref().map(|b| &b[..len])
+    }
+
+    /// Feed a source block, returning every block index newly recovered by it.
+    pub fn add_block(&mut self, index: usize, data: &[u8]) -> Vec<usize> {
+        if index >= self.k() || self.blocks[index].is_some() {
+            return Vec::new();
+        }
+        let mut block = vec![0; self.block_size];
+        let len = data.len().min(self.block_size);
+        block[..len].copy_from_slice(&data[..len]);
+        self.blocks[index] = Some(block);
+        self.peel()
+    }
+
+    /// Feed a symbol, returning every block index newly recovered by it.
+    pub fn add_symbol(&mut self, seed: u32, degree: usize, data: &[u8]) -> Vec<usize> {
+        if data.len() != self.block_size {
+            return Vec::new();
+        }
+        self.pending
+            .push((neighbours(seed, degree, self.k()), data.to_vec()));
+        self.peel()
+    }
+
+    fn peel(&mut self) -> Vec<usize> {
+        let mut recovered = Vec::new();
+        loop {
+            let mut progressed = false;
+            let mut i = 0;
+            while i < self.pending.len() {
+                let (indices, data) = &mut self.pending[i];
+                indices.retain(|&j| match &self.blocks[j] {
+                    Some(block) => {
+                        xor_into(data, block);
+                        false
+                    }
+                    None => true,
+                });
+                match indices.len() {
+                    0 => {
+                        self.pending.swap_remove(i);
+                    }
+                    1 => {
+                        let (indices, data) = self.pending.swap_remove(i);
+                        self.blocks[indices[0]] = Some(data);
+                        recovered.push(indices[0]);
+                        progressed = true;
+                    }
+                    _ => i += 1,
+                }
+            }
+            if !progressed {
+                return recovered;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_fountain() {
+    let data: Vec<u8> = (0..1234u32).map(|i| (i * 7 % 251) as u8).collect();
+    let block_size = 50;
+    let blocks: Vec<&[u8]> = data.chunks(block_size).collect();
+    let distribution = Distribution::new(blocks.len());
+
+    let mut decoder = FountainDecoder::new(data.len(), block_size);
+    let mut seed = 0;
+    while !decoder.is_complete() {
+        seed += 1;
+        assert!(seed < 1000, "fountain did not converge");
+        let degree = distribution.sample(seed);
+        decoder.add_symbol(
+            seed,
+            degree,
+            &encode(blocks.len(), |i| blocks[i], block_size, seed, degree),
+        );
+    }
+
+    let decoded: Vec<u8> = (0..decoder.k())
+        .flat_map(|i| decoder.block(i).unwrap().to_vec())
+        .collect();
+    assert_eq!(data, decoded);
+
+    // nothing to mix for an empty file
+    assert_eq!(Distribution::new(0).sample(1), 0);
+}
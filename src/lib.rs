@@ -1,15 +1,66 @@
 use dioxus::signals::{GlobalSignal, Signal};
-use indexmap::IndexMap;
 
+pub mod bundle;
+pub mod calibration;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cli;
 pub mod compress;
+pub mod crypto;
+pub mod filename;
+pub mod fountain;
+pub mod parity;
 pub mod protocol;
 pub mod receive;
 pub mod send;
+pub mod sign;
 pub mod utils;
 
-pub static QR_RES: GlobalSignal<IndexMap<String, String>> = Signal::global(IndexMap::new);
+/// Frames the sender cycles through, drawn one at a time; none shows the main page.
+pub static QR_COUNT: GlobalSignal<usize> = Signal::global(|| 0);
 pub static QR_INDEX: GlobalSignal<usize> = Signal::global(|| 0);
 pub static CAMERA_FACING: GlobalSignal<String> = Signal::global(|| "environment".to_string());
+pub static CALIBRATING: GlobalSignal<bool> = Signal::global(|| false);
+pub static ENCODER: GlobalSignal<Option<send::encoder::Encoder>> = Signal::global(|| None);
+pub static ENCODER_OPTIONS: GlobalSignal<send::encoder::EncoderOptions> =
+    Signal::global(send::encoder::EncoderOptions::default);
+
+/// Bytes that don't compress, the same every run.
+#[cfg(test)]
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545f491u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// Sends `content` through the encoder and decoder, losing the frames whose
+/// label `lose` picks, and returns the name and content received.
+#[cfg(test)]
+fn round_trip(
+    file_name: &str,
+    content: &[u8],
+    options: send::encoder::EncoderOptions,
+    lose: impl Fn(&str) -> bool,
+) -> (String, Vec<u8>) {
+    let encoder = send::encoder::Encoder::new(file_name.to_string(), content.to_vec(), options);
+    let mut decoder = receive::Decoder::new();
+    for (name, payload) in encoder.payloads() {
+        if lose(&name) {
+            continue;
+        }
+        decoder.process_bytes(&payload).unwrap();
+        if decoder.is_finished() {
+            break;
+        }
+    }
+    let res = decoder.get_finished().unwrap();
+    (res.get_name().unwrap(), res.to_bytes().unwrap())
+}
 
 #[test]
 fn test_integration() {
@@ -20,18 +71,285 @@ fn test_integration() {
 
     let int_array = Vec::from(file_content.as_bytes());
 
-    let encoder = send::encoder::Encoder::new(file_name.to_string(), int_array);
+    let encoder = send::encoder::Encoder::new(
+        file_name.to_string(),
+        int_array,
+        send::encoder::EncoderOptions::default(),
+    );
 
     let mut decoder = receive::Decoder::new();
     for (_name, payload) in encoder.payloads() {
-        decoder.process_chunk(payload);
+        decoder.process_bytes(&payload).unwrap();
     }
 
-    let res = decoder.get_finished();
+    let res = decoder.get_finished().unwrap();
 
-    let decoded_data = BASE64_STANDARD.decode(res.to_base64()).unwrap();
+    let decoded_data = BASE64_STANDARD.decode(res.to_base64().unwrap()).unwrap();
     let decoded_data = String::from_utf8(decoded_data).unwrap();
 
-    assert_eq!(file_name, res.get_name());
+    assert_eq!(file_name, res.get_name().unwrap());
     assert_eq!(file_content, decoded_data);
 }
+
+#[test]
+fn test_integration_fountain() {
+    let file_content = noise(20_000);
+    let options = send::encoder::EncoderOptions::new().fountain(100);
+    // lose every third piece, symbols fill the gaps
+    let lose = |name: &str| name.parse::<usize>().is_ok_and(|index| index % 3 == 0);
+    assert_eq!(
+        round_trip("test_fountain.bin", &file_content, options, lose),
+        ("test_fountain.bin".to_string(), file_content)
+    );
+}
+
+#[test]
+fn test_integration_parity() {
+    let file_content = noise(20_000);
+    let options = send::encoder::EncoderOptions::new().parity(20);
+    // lose a burst of pieces, parity fills the gaps
+    let lose = |name: &str| {
+        name.parse::<usize>()
+            .is_ok_and(|index| (50..70).contains(&index))
+    };
+    assert_eq!(
+        round_trip("test_parity.bin", &file_content, options, lose),
+        ("test_parity.bin".to_string(), file_content)
+    );
+}
+
+#[test]
+fn test_integration_parity_empty() {
+    let options = send::encoder::EncoderOptions::new()
+        .codec(compress::Codec::None)
+        .parity(20);
+    let encoder = send::encoder::Encoder::new("empty".to_string(), vec![], options.clone());
+    assert_eq!(encoder.frame_count(), 1);
+    assert_eq!(
+        round_trip("empty", &[], options, |_| false),
+        ("empty".to_string(), vec![])
+    );
+}
+
+#[test]
+fn test_integration_fountain_empty() {
+    let options = send::encoder::EncoderOptions::new()
+        .codec(compress::Codec::None)
+        .fountain(100);
+    let encoder = send::encoder::Encoder::new("empty".to_string(), vec![], options.clone());
+    // loops over its metadata rather than playing symbols of nothing
+    assert!(!encoder.is_endless());
+    assert_eq!(encoder.playback(5), 0);
+    assert_eq!(encoder.payload(encoder.playback(5)), encoder.payload(0));
+    assert_eq!(
+        round_trip("empty", &[], options, |_| false),
+        ("empty".to_string(), vec![])
+    );
+}
+
+#[test]
+fn test_scan_binary_frames() {
+    use image::{DynamicImage, Luma};
+    use qrcode::QrCode;
+
+    let file_name = "test_scan.txt";
+    let file_content = "Transfer your file from an air gapped computer to iOS/iPhone/iPad using only qrcode, no wifi/usb/bluetooth needed.";
+
+    let encoder = send::encoder::Encoder::new(
+        file_name.to_string(),
+        file_content.into(),
+        send::encoder::EncoderOptions::default(),
+    );
+    let mut decoder = receive::Decoder::new();
+    for (_name, payload) in encoder.payloads() {
+        let image = QrCode::new(&payload).unwrap().render::<Luma<u8>>().build();
+        let rgba = DynamicImage::ImageLuma8(image).into_rgba8();
+        assert_eq!(
+            decoder
+                .scan(rgba.width(), rgba.height(), rgba.into_raw())
+                .unwrap(),
+            1
+        );
+    }
+
+    let res = decoder.get_finished().unwrap();
+    assert_eq!(file_name, res.get_name().unwrap());
+}
+
+#[test]
+fn test_integration_base45() {
+    let file_content = "Transfer your file from an air gapped computer to iOS/iPhone/iPad using only qrcode, no wifi/usb/bluetooth needed.";
+
+    let options = send::encoder::EncoderOptions::new()
+        .encoding(protocol::Encoding::Base45)
+        .fountain(100)
+        .parity(20);
+    let (_, received) = round_trip("test_base45.txt", file_content.as_bytes(), options, |_| {
+        false
+    });
+    assert_eq!(file_content.as_bytes(), received);
+}
+
+#[test]
+fn test_integration_qr_version() {
+    use base64::{prelude::BASE64_STANDARD, Engine as _};
+    use qrcode::{QrCode, Version};
+
+    let file_name = "test_version.bin";
+    let file_content = noise(5_000);
+
+    let options = send::encoder::EncoderOptions::new()
+        .version(Some(10))
+        .ec_level(send::encoder::EcLevel::Q)
+        .parity(20);
+    let encoder = send::encoder::Encoder::new(file_name.to_string(), file_content.clone(), options);
+    assert!(encoder.chunk_size() > 100);
+
+    let mut decoder = receive::Decoder::new();
+    for (name, payload) in encoder.payloads() {
+        if name != "METADATA" {
+            assert!(
+                QrCode::with_version(&payload, Version::Normal(10), qrcode::EcLevel::Q).is_ok()
+            );
+        }
+        decoder.process_bytes(&payload).unwrap();
+    }
+
+    let res = decoder.get_finished().unwrap();
+    assert_eq!(
+        file_content,
+        BASE64_STANDARD.decode(res.to_base64().unwrap()).unwrap()
+    );
+}
+
+#[test]
+fn test_calibration_handshake() {
+    use image::{DynamicImage, Luma};
+    use qrcode::QrCode;
+
+    let options = send::encoder::EncoderOptions::default();
+    let mut decoder = receive::Decoder::new();
+    // this camera only makes out the coarser codes
+    for payload in [5, 10]
+        .into_iter()
+        .flat_map(|version| send::encoder::calibration::test_codes(version, &options))
+    {
+        let image = QrCode::new(&payload).unwrap().render::<Luma<u8>>().build();
+        let rgba = DynamicImage::ImageLuma8(image).into_rgba8();
+        assert_eq!(
+            decoder
+                .scan(rgba.width(), rgba.height(), rgba.into_raw())
+                .unwrap(),
+            1
+        );
+    }
+
+    assert_eq!(decoder.calibrated_version(), Some(10));
+    assert!(!decoder.is_finished());
+}
+
+#[test]
+fn test_integration_encrypted() {
+    use receive::DecodeError;
+
+    let file_content = b"Only for whoever knows the passphrase.".to_vec();
+    let options = send::encoder::EncoderOptions::new().passphrase(Some("hunter2".to_string()));
+    let encoder =
+        send::encoder::Encoder::new("secret.txt".to_string(), file_content.clone(), options);
+    let mut decoder = receive::Decoder::new();
+    for payload in encoder.payloads().values() {
+        // nothing of the content is readable from the codes
+        assert!(!payload.windows(4).any(|window| window == b"pass"));
+        decoder.process_bytes(payload).unwrap();
+    }
+
+    let mut res = decoder.get_finished().unwrap();
+    assert!(res.is_encrypted());
+    assert_eq!(res.to_bytes(), Err(DecodeError::PassphraseRequired));
+    assert_eq!(res.unlock("hunter3"), Err(DecodeError::Decryption));
+    assert_eq!(res.to_bytes(), Err(DecodeError::PassphraseRequired));
+    res.unlock("hunter2").unwrap();
+    assert_eq!(res.to_bytes().unwrap(), file_content);
+}
+
+#[test]
+fn test_integration_signed() {
+    use sign::Verification;
+
+    let key = sign::generate_key();
+    let options = send::encoder::EncoderOptions::new().signing_key(Some(key.clone()));
+    let encoder = send::encoder::Encoder::new("build.bin".to_string(), vec![7; 500], options);
+    let payloads = encoder.payloads();
+    assert_eq!(payloads.get_index(1).unwrap().0, "SIGNATURE");
+
+    let receive = |trusted: &[&str], skip_signature: bool| {
+        let mut decoder = receive::Decoder::new();
+        for key in trusted {
+            decoder.trust_key(key).unwrap();
+        }
+        for (name, payload) in &payloads {
+            if !(skip_signature && name == "SIGNATURE") {
+                decoder.process_bytes(payload).unwrap();
+            }
+        }
+        decoder
+    };
+
+    let public = sign::to_hex(key.verifying_key().as_bytes());
+    let finished = receive(&[&public], false).get_finished().unwrap();
+    assert_eq!(
+        finished.verification(),
+        Verification::Trusted(public[..16].to_string())
+    );
+    assert_eq!(finished.to_bytes().unwrap(), vec![7; 500]);
+
+    let other = sign::to_hex(sign::generate_key().verifying_key().as_bytes());
+    let finished = receive(&[&other], false).get_finished().unwrap();
+    assert!(matches!(
+        finished.verification(),
+        Verification::Untrusted(_)
+    ));
+
+    // a signed transfer is not complete without its signature
+    let decoder = receive(&[&public], true);
+    assert!(!decoder.is_finished());
+    assert!(decoder.get_progress().contains("expecting: SIGNATURE."));
+}
+
+#[test]
+fn test_integration_hash_algorithms() {
+    use utils::HashAlgorithm;
+
+    for algorithm in [
+        HashAlgorithm::Sha1,
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Blake3,
+    ] {
+        let options = send::encoder::EncoderOptions::new().hash_algorithm(algorithm);
+        let (_, received) = round_trip("hashed.txt", &[1; 300], options, |_| false);
+        assert_eq!(received, vec![1; 300]);
+    }
+}
+
+#[test]
+fn test_integration_codecs() {
+    use compress::Codec;
+
+    let text = b"Transfer your file from an air gapped computer. ".repeat(20);
+    let random = noise(2_000);
+
+    for (codec, content, sent_as) in [
+        (Codec::Zstd, &text, Codec::Zstd),
+        (Codec::Deflate(9), &text, Codec::Deflate(9)),
+        (Codec::None, &text, Codec::None),
+        // already compressed data is stored as is
+        (Codec::default(), &random, Codec::None),
+    ] {
+        let options = send::encoder::EncoderOptions::new().codec(codec);
+        let encoder =
+            send::encoder::Encoder::new("codec.bin".to_string(), content.clone(), options.clone());
+        assert_eq!(encoder.metadata().codec, sent_as);
+        let (_, received) = round_trip("codec.bin", content, options, |_| false);
+        assert_eq!(&received, content);
+    }
+}
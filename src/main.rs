@@ -1,11 +1,13 @@
 #![allow(non_snake_case)]
 
 use dioxus::prelude::*;
-use qrtransfer::receive::{start_receiving, stop_receiving, switch_camera};
+use qrtransfer::receive::{
+    set_trusted_keys, start_receiving, stop_receiving, switch_camera, trusted_keys,
+};
 
 use qrtransfer::send::{self, QrResPage};
 use qrtransfer::utils::{log, set_panic_hook};
-use qrtransfer::QR_RES;
+use qrtransfer::{ENCODER_OPTIONS, QR_COUNT};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 
@@ -40,8 +42,8 @@ fn app() -> Element {
         html.set_attribute("data-bs-theme", &theme.read()).unwrap();
     });
 
-    let payloads = QR_RES.read().clone();
-    if payloads.is_empty() {
+    let total = *QR_COUNT.read();
+    if total == 0 {
         rsx! {
             div { id: "outer-div",
                 div { id: "middle-div",
@@ -85,16 +87,64 @@ fn app() -> Element {
                                     id: "send",
                                     "aria-labelledby": "send-tab",
                                     role: "tabpanel",
+                                    input {
+                                        class: "form-control mb-2",
+                                        id: "passphrase",
+                                        placeholder: "Passphrase to encrypt with (optional)",
+                                        autocomplete: "new-password",
+                                        oninput: move |evt| {
+                                            let options = ENCODER_OPTIONS.read().clone();
+                                            *ENCODER_OPTIONS.write() = options.passphrase(Some(evt.value()));
+                                        },
+                                        r#type: "password",
+                                    }
+                                    select {
+                                        class: "form-select mb-2",
+                                        id: "compression",
+                                        onchange: move |evt| {
+                                            let options = ENCODER_OPTIONS.read().clone();
+                                            if let Ok(codec) = evt.value().parse() {
+                                                *ENCODER_OPTIONS.write() = options.codec(codec);
+                                            }
+                                        },
+                                        option { value: "brotli-11", "Brotli, smallest (default, Zstandard above 20 MB)" }
+                                        option { value: "brotli-5", "Brotli, faster" }
+                                        option { value: "zstd", "Zstandard, fastest" }
+                                        option { value: "deflate-6", "Deflate" }
+                                        option { value: "none", "No compression" }
+                                    }
                                     input {
                                         class: "form-control form-control-lg",
                                         id: "file-selector",
+                                        multiple: true,
+                                        onchange: move |_| {
+                                            spawn(async move {
+                                                send::read_file_content("file-selector").await;
+                                            });
+                                        },
+                                        r#type: "file",
+                                    }
+                                    label {
+                                        class: "form-label mt-2",
+                                        r#for: "folder-selector",
+                                        "Or a whole folder:"
+                                    }
+                                    input {
+                                        class: "form-control",
+                                        id: "folder-selector",
+                                        "webkitdirectory": "true",
                                         onchange: move |_| {
                                             spawn(async move {
-                                                send::read_file_content().await;
+                                                send::read_file_content("folder-selector").await;
                                             });
                                         },
                                         r#type: "file",
                                     }
+                                    button {
+                                        class: "btn btn-outline-secondary btn-sm mt-2",
+                                        onclick: move |_| send::calibrate(),
+                                        "Calibrate"
+                                    }
                                     div { id: "progress" }
                                     div { id: "qrcode" }
                                 }
@@ -132,6 +182,17 @@ fn app() -> Element {
                                         onclick: move |_| stop_receiving(),
                                         "Stop"
                                     }
+                                    details { class: "mt-2",
+                                        summary { "Trusted senders" }
+                                        textarea {
+                                            class: "form-control font-monospace",
+                                            id: "trusted-keys",
+                                            rows: "3",
+                                            placeholder: "Public keys from qrtransfer keygen, one per line",
+                                            initial_value: trusted_keys(),
+                                            oninput: move |evt| set_trusted_keys(&evt.value()),
+                                        }
+                                    }
                                 }
                             }
                             a { href: "https://github.com/WestXu/qrtransfer",
@@ -149,12 +210,17 @@ fn app() -> Element {
         }
     } else {
         rsx! {
-            QrResPage { payloads }
+            QrResPage { total }
         }
     }
 }
 
 fn main() {
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::env::args().len() > 1 {
+        std::process::exit(qrtransfer::cli::main());
+    }
+
     set_panic_hook();
 
     web_sys::window()
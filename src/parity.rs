@@ -0,0 +1,254 @@
+//! Reed-Solomon parity pieces over fixed-size blocks.
+//!
+//! GF(2^8) codes are limited to 256 shards, and setting a code up is cubic in
+//! its size, so the blocks are interleaved into `groups` of at most
+//! `MAX_GROUP` blocks (block `i` belongs to group `i % groups`) and every group
+//! gets its own parity shards. Interleaving also spreads a burst of missed
+//! frames over several groups.
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use std::collections::HashMap;
+
+const MAX_SHARDS: usize = 256;
+const MAX_GROUP: usize = 64;
+
+/// Parity shards protecting `data` blocks at `overhead` percent.
+pub fn parity_count(data: usize, overhead: usize) -> usize {
+    (data * overhead).div_ceil(100).max(1)
+}
+
+/// Number of groups needed to keep every group within the GF(2^8) limit.
+pub fn groups(k: usize, overhead: usize) -> usize {
+    let mut per_group = MAX_GROUP;
+    while per_group + parity_count(per_group, overhead) > MAX_SHARDS {
+        per_group -= 1;
+    }
+    k.div_ceil(per_group).max(1)
+}
+
+/// The block indices belonging to `group`.
+pub fn members(k: usize, groups: usize, group: usize) -> Vec<usize> {
+    (group..k).step_by(groups).collect()
+}
+
+/// Whether `encode` lays out `k` blocks in `groups` groups, with `total`
+/// parity shards for `group`, at some overhead. Frames that don't are
+/// forged or misread and must not size a `ParityDecoder`.
+pub fn is_layout(k: usize, groups: usize, group: usize, total: usize) -> bool {
+    if groups == 0 || group >= groups || groups > k || total == 0 {
+        return false;
+    }
+    let members = (k - group).div_ceil(groups);
+    if members > MAX_GROUP || total > MAX_SHARDS - members {
+        return false;
+    }
+    // the overheads giving this group `total` shards
+    let lowest = ((total - 1) * 100 / members).max(1);
+    let highest = total * 100 / members;
+    (lowest..=highest).any(|overhead| {
+        parity_count(members, overhead) == total && self::groups(k, overhead) == groups
+    })
+}
+
+fn pad(data: &[u8], block_size: usize) -> Vec<u8> {
+    let mut block = data.to_vec();
+    block.resize(block_size, 0);
+    block
+}
+
+/// One parity shard.
+#[derive(Clone)]
+pub struct Shard {
+    pub group: usize,
+    pub total: usize,
+    pub index: usize,
+    pub data: Vec<u8>,
+}
+
+/// Parity shards for `blocks`, zero-padding the short last block.
+pub fn encode(blocks: &[&[u8]], block_size: usize, overhead: usize) -> Vec<Shard> {
+    (0..groups(blocks.len(), overhead))
+        .flat_map(|group| encode_group(blocks.len(), |i| blocks[i], block_size, overhead, group))
+        .collect()
+}
+
+/// The parity shards of a single group of the `k` blocks given by `block`, so
+/// they can be made when needed.
+pub fn encode_group<'a>(
+    k: usize,
+    block: impl Fn(usize) -> &'a [u8],
+    block_size: usize,
+    overhead: usize,
+    group: usize,
+) -> Vec<Shard> {
+    let members = members(k, groups(k, overhead), group);
+    let total = parity_count(members.len(), overhead);
+    let rs = ReedSolomon::new(members.len(), total).unwrap();
+
+    let mut group_shards: Vec<Vec<u8>> = members
+        .iter()
+        .map(|&i| pad(block(i), block_size))
+        .chain((0..total).map(|_| vec![0; block_size]))
+        .collect();
+    rs.encode(&mut group_shards).unwrap();
+
+    group_shards
+        .into_iter()
+        .skip(members.len())
+        .enumerate()
+        .map(|(index, data)| Shard {
+            group,
+            total,
+            index,
+            data,
+        })
+        .collect()
+}
+
+/// Rebuilds missing blocks once a group holds as many shards as it has blocks.
+pub struct ParityDecoder {
+    size: usize,
+    block_size: usize,
+    groups: usize,
+    blocks: Vec<Option<Vec<u8>>>,
+    parity: HashMap<usize, Vec<Option<Vec<u8>>>>,
+}
+
+impl ParityDecoder {
+    pub fn new(size: usize, block_size: usize, groups: usize) -> Self {
+        let k = size.div_ceil(block_size);
+        ParityDecoder {
+            size,
+            block_size,
+            groups,
+            blocks: vec![None; k],
+            parity: HashMap::new(),
+        }
+    }
+
+    pub fn k(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn groups(&self) -> usize {
+        self.groups
+    }
+
+    /// The recovered block, trimmed to its real length.
+    pub fn block(&self, index: usize) -> Option<&[u8]> {
+        let end = ((index + 1) * self.block_size).min(self.size);
+        let len = end.saturating_sub(index * self.block_size);
+        self.blocks.get(index)?.as_ref().map(|b| &b[..len])
+    }
+
+    /// Feed a source block, returning every block index newly recovered by it.
+    pub fn add_block(&mut self, index: usize, data: &[u8]) -> Vec<usize> {
+        if index >= self.k() || self.blocks[index].is_some() || data.len() > self.block_size {
+            return Vec::new();
+        }
+        self.blocks[index] = Some(pad(data, self.block_size));
+        self.reconstruct(index % self.groups)
+    }
+
+    /// Feed a parity shard, returning every block index newly recovered by it.
+    pub fn add_parity(
+        &mut self,
+        group: usize,
+        total: usize,
+        index: usize,
+        data: &[u8],
+    ) -> Vec<usize> {
+        if !is_layout(self.k(), self.groups, group, total)
+            || index >= total
+            || data.len() != self.block_size
+        {
+            return Vec::new();
+        }
+        let shards = self
+            .parity
+            .entry(group)
+            .or_insert_with(|| vec![None; total]);
+        if shards.len() != total || shards[index].is_some() {
+            return Vec::new();
+        }
+        shards[index] = Some(data.to_vec());
+        self.reconstruct(group)
+    }
+
+    fn reconstruct(&mut self, group: usize) -> Vec<usize> {
+        let Some(parity) = self.parity.get(&group) else {
+            return Vec::new();
+        };
+        let members = members(self.k(), self.groups, group);
+        let missing: Vec<usize> = members
+            .iter()
+            .copied()
+            .filter(|&i| self.blocks[i].is_none())
+            .collect();
+        let available = parity.iter().filter(|s| s.is_some()).count();
+        if missing.is_empty() || available < missing.len() {
+            return Vec::new();
+        }
+
+        let Ok(rs) = ReedSolomon::new(members.len(), parity.len()) else {
+            return Vec::new();
+        };
+        let mut shards: Vec<Option<Vec<u8>>> = members
+            .iter()
+            .map(|&i| self.blocks[i].clone())
+            .chain(parity.iter().cloned())
+            .collect();
+        if rs.reconstruct_data(&mut shards).is_err() {
+            return Vec::new();
+        }
+
+        for (&i, shard) in members.iter().zip(shards) {
+            if self.blocks[i].is_none() {
+                self.blocks[i] = shard;
+            }
+        }
+        missing
+    }
+}
+
+#[test]
+fn test_parity() {
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let block_size = 100;
+    let blocks: Vec<&[u8]> = data.chunks(block_size).collect();
+    let shards = encode(&blocks, block_size, 20);
+    let groups = groups(blocks.len(), 20);
+    assert!(groups > 1);
+
+    let mut decoder = ParityDecoder::new(data.len(), block_size, groups);
+    // a burst of 20 consecutive missing blocks, spread over the groups
+    for (i, block) in blocks.iter().enumerate() {
+        if !(100..120).contains(&i) {
+            decoder.add_block(i, block);
+        }
+    }
+    for shard in &shards {
+        decoder.add_parity(shard.group, shard.total, shard.index, &shard.data);
+    }
+
+    let decoded: Vec<u8> = (0..decoder.k())
+        .flat_map(|i| decoder.block(i).unwrap().to_vec())
+        .collect();
+    assert_eq!(data, decoded);
+
+    for shard in &shards {
+        assert!(is_layout(blocks.len(), groups, shard.group, shard.total));
+    }
+    assert!(!is_layout(blocks.len(), 0, 0, 10));
+    assert!(!is_layout(blocks.len(), groups, groups, 10));
+    assert!(!is_layout(blocks.len(), groups, 0, 1 << 40));
+    assert!(!is_layout(blocks.len(), 1, 0, 10));
+}
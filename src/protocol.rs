@@ -1,3 +1,8 @@
+use crate::compress::Codec;
+use crate::utils::HashAlgorithm;
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::str::FromStr;
 
@@ -5,18 +10,280 @@ use std::str::FromStr;
 pub struct Metadata {
     pub name: String,
     pub length: usize,
+    /// Hex digest of the data sent.
     pub hash: String,
+    pub hash_algorithm: HashAlgorithm,
+    /// How the data sent was compressed.
+    pub codec: Codec,
+    /// Size of the file before compression, unknown from older senders.
+    pub size: Option<usize>,
+    pub info: FileInfo,
+    /// Options this receiver doesn't know, from newer senders. Kept so the
+    /// metadata compares and verifies as it was sent.
+    pub extra: Options,
+    /// The pieces are encrypted with a passphrase, see `crypto`.
+    pub encrypted: bool,
+    /// A signature frame comes with the transfer, see `sign`.
+    pub signed: bool,
 }
 
+/// What the sender knows about a single file besides its content, restored
+/// by the receiver where it can.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileInfo {
+    /// Last modification, in seconds since the epoch.
+    pub mtime: Option<u64>,
+    /// Unix permission bits.
+    pub mode: Option<u32>,
+    /// As declared by the sender, see `mime_essence`.
+    pub mime: Option<String>,
+}
+
+/// The `type/subtype` of a MIME type, lowercase and without parameters, or
+/// `None` if it isn't one.
+pub fn mime_essence(mime: &str) -> Option<String> {
+    let essence = mime.split(';').next()?.trim().to_ascii_lowercase();
+    let token = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+    };
+    let (kind, subtype) = essence.split_once('/')?;
+    (token(kind) && token(subtype)).then_some(essence)
+}
+
+/// Every frame starts with `QRT:<version>:`, followed by `<session>:<type>:`
+/// since version 2 and by `<type>:` in version 1.
+pub const MAGIC: &str = "QRT";
+pub const VERSION: u32 = 2;
+/// Fewest bytes a sender puts into every piece but the last, so the piece
+/// count a receiver has to expect is bounded by the file size.
+pub const MIN_CHUNK_SIZE: usize = 16;
+/// Largest number a frame field carries, binary frames hold them as `u32`.
+pub const MAX_FIELD: usize = u32::MAX as usize;
+
 impl Metadata {
+    /// Metadata with a SHA-1 hash of Brotli data, as older senders sent.
     pub fn new(name: String, length: usize, hash: String) -> Self {
-        Self { name, length, hash }
+        Self {
+            name,
+            length,
+            hash,
+            hash_algorithm: HashAlgorithm::Sha1,
+            codec: Codec::default(),
+            size: None,
+            info: FileInfo::default(),
+            extra: Options::default(),
+            encrypted: false,
+            signed: false,
+        }
+    }
+
+    /// The options differing from the defaults, which the baseline receiver
+    /// can't read, and the unknown ones kept from the sender.
+    fn options(&self) -> Options {
+        let mut options = self.extra.clone();
+        if self.hash_algorithm != HashAlgorithm::Sha1 {
+            options.set("h", self.hash_algorithm.name());
+        }
+        if self.codec != Codec::default() {
+            options.set("c", self.codec.to_string());
+        }
+        if let Some(size) = self.size {
+            options.set("s", size.to_string());
+        }
+        if let Some(mtime) = self.info.mtime {
+            options.set("t", mtime.to_string());
+        }
+        if let Some(mode) = self.info.mode {
+            options.set("mode", format!("{:o}", mode));
+        }
+        if let Some(mime) = &self.info.mime {
+            options.set("mime", mime.clone());
+        }
+        if self.encrypted {
+            options.set("enc", crate::crypto::SCHEME);
+        }
+        if self.signed {
+            options.set("sig", "ed25519");
+        }
+        options
+    }
+
+    /// `name,length,hash`, followed by the options.
+    fn body(&self) -> String {
+        let mut body = format!("{},{},{}", self.name, self.length, self.hash);
+        let options = self.options();
+        if !options.is_empty() {
+            body.push_str(&format!(",{}", options));
+        }
+        body
+    }
+
+    fn from_body(data: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = data.split(',').collect();
+
+        if parts.len() < 3 {
+            return Err(format!("Expected 3 parts, got {}", parts.len()));
+        }
+
+        let length = parts[1]
+            .parse::<usize>()
+            .map_err(|e| format!("Failed to parse length: {}", e))?;
+
+        let mut options = Options::parse(&parts[3..])?;
+        let mut metadata = Metadata::new(parts[0].to_string(), length, parts[2].to_string());
+        match options.take("enc").as_deref() {
+            Some(crate::crypto::SCHEME) => metadata.encrypted = true,
+            Some(scheme) => return Err(format!("Unknown encryption '{}'", scheme)),
+            None => {}
+        }
+        if let Some(algorithm) = options.take("h") {
+            metadata.hash_algorithm = algorithm.parse()?;
+        }
+        if let Some(codec) = options.take("c") {
+            metadata.codec = codec.parse()?;
+        }
+        if let Some(size) = options.take("s") {
+            let size = size
+                .parse()
+                .map_err(|e| format!("Failed to parse size: {}", e))?;
+            metadata.size = Some(size);
+        }
+        if let Some(mtime) = options.take("t") {
+            let mtime = mtime
+                .parse()
+                .map_err(|e| format!("Failed to parse mtime: {}", e))?;
+            metadata.info.mtime = Some(mtime);
+        }
+        if let Some(mode) = options.take("mode") {
+            let mode = u32::from_str_radix(&mode, 8)
+                .map_err(|e| format!("Failed to parse mode: {}", e))?;
+            metadata.info.mode = Some(mode);
+        }
+        if let Some(mime) = options.take("mime") {
+            let essence = mime_essence(&mime)
+                .filter(|essence| *essence == mime)
+                .ok_or(format!("Invalid MIME type '{}'", mime))?;
+            metadata.info.mime = Some(essence);
+        }
+        match options.take("sig").as_deref() {
+            Some("ed25519") => metadata.signed = true,
+            Some(scheme) => return Err(format!("Unknown signature '{}'", scheme)),
+            None => {}
+        }
+        metadata.extra = options;
+        Ok(metadata)
+    }
+}
+
+/// The extensible part of the metadata: `key=value` options after
+/// `name,length,hash`. Keys are short lowercase words and values
+/// percent-escaped text; they are written sorted by key, so metadata always
+/// reads back, and is signed, the same. Receivers keep the keys they don't
+/// know, so new features can add options without breaking them. The baseline
+/// receiver reads exactly three parts, so senders only set options that
+/// differ from the defaults.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Options(BTreeMap<String, String>);
+
+impl Options {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    pub fn set(&mut self, key: &str, value: impl Into<String>) {
+        assert!(is_key(key), "invalid option key '{}'", key);
+        self.0.insert(key.to_string(), value.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn take(&mut self, key: &str) -> Option<String> {
+        self.0.remove(key)
+    }
+
+    fn parse(parts: &[&str]) -> Result<Self, String> {
+        let mut options = BTreeMap::new();
+        for option in parts {
+            let (key, value) = option
+                .split_once('=')
+                .filter(|(key, _)| is_key(key))
+                .ok_or(format!("Expected key=value, got '{}'", option))?;
+            if options.insert(key.to_string(), unescape(value)?).is_some() {
+                return Err(format!("Option '{}' given twice", key));
+            }
+        }
+        Ok(Options(options))
+    }
+}
+
+impl fmt::Display for Options {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}", key, escape(value))?;
+        }
+        Ok(())
+    }
+}
+
+/// Option keys are short lowercase words.
+fn is_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+/// Percent-escapes what would end an option early, so values can be any text.
+fn escape(value: &str) -> Cow<'_, str> {
+    let special = |c: char| matches!(c, '%' | ',' | '=') || c.is_control();
+    if !value.contains(special) {
+        return value.into();
     }
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if special(c) {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                escaped.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped.into()
 }
 
+fn unescape(value: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, next)) = rest.split_first() {
+        rest = next;
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let escaped = rest
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or(format!("Invalid escape in '{}'", value))?;
+        bytes.push(escaped);
+        rest = &rest[2..];
+    }
+    String::from_utf8(bytes).map_err(|e| format!("Invalid escape in '{}': {}", value, e))
+}
+
+/// The legacy, unversioned `METADATA:` frame.
 impl fmt::Display for Metadata {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "METADATA:{},{},{}", self.name, self.length, self.hash)
+        write!(f, "METADATA:{}", self.body())
     }
 }
 
@@ -24,51 +291,491 @@ impl FromStr for Metadata {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if !s.starts_with("METADATA:") {
-            return Err("Metadata must start with 'METADATA:'".to_string());
+        match s.strip_prefix("METADATA:") {
+            Some(data) => Metadata::from_body(data),
+            None => Err("Metadata must start with 'METADATA:'".to_string()),
         }
+    }
+}
 
-        let data = &s[9..];
-        let parts: Vec<&str> = data.split(',').collect();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameType {
+    Metadata,
+    Piece,
+    Symbol,
+    Parity,
+    Calibration,
+    Signature,
+}
 
-        if parts.len() != 3 {
-            return Err(format!("Expected 3 parts, got {}", parts.len()));
+impl FrameType {
+    pub fn code(&self) -> char {
+        match self {
+            FrameType::Metadata => 'M',
+            FrameType::Piece => 'P',
+            FrameType::Symbol => 'S',
+            FrameType::Parity => 'R',
+            FrameType::Calibration => 'C',
+            FrameType::Signature => 'G',
         }
+    }
+}
 
-        let length = parts[1]
-            .parse::<usize>()
-            .map_err(|e| format!("Failed to parse length: {}", e))?;
+impl FromStr for FrameType {
+    type Err = String;
 
-        Ok(Metadata {
-            name: parts[0].to_string(),
-            length,
-            hash: parts[2].to_string(),
-        })
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "M" => Ok(FrameType::Metadata),
+            "P" => Ok(FrameType::Piece),
+            "S" => Ok(FrameType::Symbol),
+            "R" => Ok(FrameType::Parity),
+            "C" => Ok(FrameType::Calibration),
+            "G" => Ok(FrameType::Signature),
+            _ => Err(format!("Unknown frame type '{}'", s)),
+        }
+    }
+}
+
+/// How frames are put into QR codes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// `QRT:` text frames with base64 data.
+    Text,
+    /// `QRT:` text frames with base45 data, for QR alphanumeric mode.
+    Base45,
+    /// Raw bytes in QR byte mode, a third smaller than base64.
+    #[default]
+    Binary,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" | "base64" => Ok(Encoding::Text),
+            "base45" => Ok(Encoding::Base45),
+            "binary" => Ok(Encoding::Binary),
+            _ => Err(format!("Unknown encoding '{}'", s)),
+        }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Message {
     Metadata(Metadata),
-    Piece { index: usize, data: String },
+    /// Piece `index` of the data, counting from 1. The CRC32 of the data
+    /// catches codes misread by the camera; older senders had none.
+    Piece {
+        index: usize,
+        data: Vec<u8>,
+        checksum: Option<u32>,
+    },
+    Symbol {
+        seed: u32,
+        degree: usize,
+        size: usize,
+        data: Vec<u8>,
+    },
+    Parity {
+        size: usize,
+        groups: usize,
+        group: usize,
+        total: usize,
+        index: usize,
+        data: Vec<u8>,
+    },
+    /// Test code `index` of `total` filling QR `version`, see `calibration`.
+    Calibration {
+        version: usize,
+        index: usize,
+        total: usize,
+        data: Vec<u8>,
+    },
+    /// The sender's Ed25519 public key and its signature of the metadata, see
+    /// `sign`.
+    Signature {
+        key: Vec<u8>,
+        signature: Vec<u8>,
+    },
 }
 
-impl fmt::Display for Message {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Message {
+    /// A piece with its checksum, as senders send it.
+    pub fn piece(index: usize, data: Vec<u8>) -> Self {
+        let checksum = Some(crc32fast::hash(&data));
+        Message::Piece {
+            index,
+            data,
+            checksum,
+        }
+    }
+
+    /// False for a piece whose data does not match its checksum.
+    pub fn is_intact(&self) -> bool {
         match self {
-            Message::Metadata(metadata) => write!(f, "{}", metadata),
-            Message::Piece { index, data } => write!(f, "{}:{}", index, data),
+            Message::Piece {
+                data,
+                checksum: Some(checksum),
+                ..
+            } => crc32fast::hash(data) == *checksum,
+            _ => true,
         }
     }
-}
 
-impl FromStr for Message {
-    type Err = String;
+    pub fn frame_type(&self) -> FrameType {
+        match self {
+            Message::Metadata(_) => FrameType::Metadata,
+            Message::Piece { .. } => FrameType::Piece,
+            Message::Symbol { .. } => FrameType::Symbol,
+            Message::Parity { .. } => FrameType::Parity,
+            Message::Calibration { .. } => FrameType::Calibration,
+            Message::Signature { .. } => FrameType::Signature,
+        }
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.starts_with("METADATA:") {
-            let metadata = Metadata::from_str(s)?;
-            Ok(Message::Metadata(metadata))
+    /// A short name for the message, as the sender labels its codes.
+    pub fn label(&self) -> String {
+        match self {
+            Message::Metadata(_) => "METADATA".to_string(),
+            Message::Piece { index, .. } => index.to_string(),
+            Message::Symbol { seed, .. } => format!("S{}", seed),
+            Message::Parity { group, index, .. } => format!("R{}.{}", group, index),
+            Message::Calibration { version, index, .. } => format!("C{}.{}", version, index + 1),
+            Message::Signature { .. } => "SIGNATURE".to_string(),
+        }
+    }
+
+    fn body(&self) -> String {
+        match self {
+            Message::Metadata(metadata) => metadata.body(),
+            Message::Piece {
+                index,
+                data,
+                checksum: None,
+            } => format!("{},{}", index, BASE64_STANDARD.encode(data)),
+            Message::Piece {
+                index,
+                data,
+                checksum: Some(checksum),
+            } => format!(
+                "{},{},{:08x}",
+                index,
+                BASE64_STANDARD.encode(data),
+                checksum
+            ),
+            Message::Symbol {
+                seed,
+                degree,
+                size,
+                data,
+            } => format!(
+                "{},{},{},{}",
+                seed,
+                degree,
+                size,
+                BASE64_STANDARD.encode(data)
+            ),
+            Message::Parity {
+                size,
+                groups,
+                group,
+                total,
+                index,
+                data,
+            } => format!(
+                "{},{},{},{},{},{}",
+                size,
+                groups,
+                group,
+                total,
+                index,
+                BASE64_STANDARD.encode(data)
+            ),
+            Message::Calibration {
+                version,
+                index,
+                total,
+                data,
+            } => format!(
+                "{},{},{},{}",
+                version,
+                index,
+                total,
+                BASE64_STANDARD.encode(data)
+            ),
+            Message::Signature { key, signature } => format!(
+                "{},{}",
+                BASE64_STANDARD.encode(key),
+                BASE64_STANDARD.encode(signature)
+            ),
+        }
+    }
+
+    /// The numeric fields and the data of a non-metadata message.
+    fn fields(&self) -> Option<(Vec<usize>, Cow<'_, [u8]>)> {
+        match self {
+            Message::Metadata(_) => None,
+            Message::Piece {
+                index,
+                data,
+                checksum,
+            } => {
+                let checksum = checksum.unwrap_or_else(|| crc32fast::hash(data));
+                Some((vec![*index, checksum as usize], data.into()))
+            }
+            Message::Symbol {
+                seed,
+                degree,
+                size,
+                data,
+            } => Some((vec![*seed as usize, *degree, *size], data.into())),
+            Message::Parity {
+                size,
+                groups,
+                group,
+                total,
+                index,
+                data,
+            } => Some((vec![*size, *groups, *group, *total, *index], data.into())),
+            Message::Calibration {
+                version,
+                index,
+                total,
+                data,
+            } => Some((vec![*version, *index, *total], data.into())),
+            // the key length, then key and signature as one
+            Message::Signature { key, signature } => {
+                Some((vec![key.len()], [key.as_slice(), signature].concat().into()))
+            }
+        }
+    }
+
+    fn field_count(frame_type: FrameType) -> usize {
+        match frame_type {
+            FrameType::Metadata => 0,
+            FrameType::Piece => 2,
+            FrameType::Symbol => 3,
+            FrameType::Parity => 5,
+            FrameType::Calibration => 3,
+            FrameType::Signature => 1,
+        }
+    }
+
+    fn from_fields(frame_type: FrameType, fields: &[usize], data: Vec<u8>) -> Result<Self, String> {
+        let small = |field: usize| {
+            u32::try_from(field).map_err(|_| format!("Field {} is out of range", field))
+        };
+        Ok(match frame_type {
+            FrameType::Metadata => unreachable!("metadata has no numeric fields"),
+            FrameType::Piece => Message::Piece {
+                index: fields[0],
+                data,
+                checksum: Some(small(fields[1])?),
+            },
+            FrameType::Symbol => Message::Symbol {
+                seed: small(fields[0])?,
+                degree: fields[1],
+                size: fields[2],
+                data,
+            },
+            FrameType::Parity => Message::Parity {
+                size: fields[0],
+                groups: fields[1],
+                group: fields[2],
+                total: fields[3],
+                index: fields[4],
+                data,
+            },
+            FrameType::Calibration => Message::Calibration {
+                version: fields[0],
+                index: fields[1],
+                total: fields[2],
+                data,
+            },
+            FrameType::Signature => {
+                let (key, signature) = data.split_at(fields[0].min(data.len()));
+                Message::Signature {
+                    key: key.to_vec(),
+                    signature: signature.to_vec(),
+                }
+            }
+        })
+    }
+
+    /// The body of a binary frame: big-endian `u32` fields followed by the raw
+    /// data. Metadata is sent once per loop and keeps its text body. Fails
+    /// for a field above `MAX_FIELD`.
+    fn binary_body(&self) -> Result<Vec<u8>, String> {
+        let Some((fields, data)) = self.fields() else {
+            return Ok(self.body().into_bytes());
+        };
+        let mut body = Vec::with_capacity(4 * fields.len() + data.len());
+        for field in fields {
+            let field = u32::try_from(field)
+                .map_err(|_| format!("Field {} doesn't fit a binary frame", field))?;
+            body.extend_from_slice(&field.to_be_bytes());
+        }
+        body.extend_from_slice(&data);
+        Ok(body)
+    }
+
+    fn from_binary_body(frame_type: FrameType, body: &[u8]) -> Result<Self, String> {
+        if frame_type == FrameType::Metadata {
+            let body = std::str::from_utf8(body)
+                .map_err(|e| format!("Failed to parse metadata: {}", e))?;
+            return Ok(Message::Metadata(Metadata::from_body(body)?));
+        }
+
+        let count = Message::field_count(frame_type);
+        if body.len() < 4 * count {
+            return Err("Frame is truncated".to_string());
+        }
+        let (head, data) = body.split_at(4 * count);
+        let fields = head
+            .chunks(4)
+            .map(|field| u32::from_be_bytes(field.try_into().unwrap()) as usize)
+            .collect::<Vec<usize>>();
+        Message::from_fields(frame_type, &fields, data.to_vec())
+    }
+
+    /// The body of a base45 frame: `:`-separated fields and base45 data, so the
+    /// whole frame stays within the QR alphanumeric charset. Metadata has no
+    /// base45 form.
+    fn base45_body(&self) -> Option<String> {
+        let (fields, data) = self.fields()?;
+        let mut parts = fields
+            .into_iter()
+            .map(|field| field.to_string())
+            .collect::<Vec<String>>();
+        parts.push(base45::encode(&data));
+        Some(parts.join(":"))
+    }
+
+    fn from_base45_body(frame_type: FrameType, body: &str) -> Result<Self, String> {
+        let count = Message::field_count(frame_type);
+        if count == 0 {
+            return Err(format!("{} frame has no base45 form", frame_type.code()));
+        }
+
+        let parts: Vec<&str> = body.splitn(count + 1, ':').collect();
+        if parts.len() != count + 1 {
+            return Err(format!("Expected {} parts, got {}", count + 1, parts.len()));
+        }
+        let fields = parts[..count]
+            .iter()
+            .map(|part| part.parse::<usize>())
+            .collect::<Result<Vec<usize>, _>>()
+            .map_err(|e| format!("Failed to parse fields: {}", e))?;
+        let data =
+            base45::decode(parts[count]).map_err(|e| format!("Failed to decode data: {}", e))?;
+        Message::from_fields(frame_type, &fields, data)
+    }
+
+    fn from_body(frame_type: FrameType, data: &str) -> Result<Self, String> {
+        let decode = |data: &str| {
+            BASE64_STANDARD
+                .decode(data)
+                .map_err(|e| format!("Failed to decode data: {}", e))
+        };
+
+        match frame_type {
+            FrameType::Metadata => Ok(Message::Metadata(Metadata::from_body(data)?)),
+            FrameType::Piece => {
+                let parts: Vec<&str> = data.split(',').collect();
+                if !(2..=3).contains(&parts.len()) {
+                    return Err(format!("Expected 2 or 3 parts, got {}", parts.len()));
+                }
+                let checksum = parts
+                    .get(2)
+                    .map(|checksum| u32::from_str_radix(checksum, 16))
+                    .transpose()
+                    .map_err(|e| format!("Failed to parse checksum: {}", e))?;
+                Ok(Message::Piece {
+                    index: parts[0]
+                        .parse()
+                        .map_err(|e| format!("Failed to parse index: {}", e))?,
+                    data: decode(parts[1])?,
+                    checksum,
+                })
+            }
+            FrameType::Symbol => {
+                let parts: Vec<&str> = data.split(',').collect();
+                if parts.len() != 4 {
+                    return Err(format!("Expected 4 parts, got {}", parts.len()));
+                }
+
+                Ok(Message::Symbol {
+                    seed: parts[0]
+                        .parse()
+                        .map_err(|e| format!("Failed to parse seed: {}", e))?,
+                    degree: parts[1]
+                        .parse()
+                        .map_err(|e| format!("Failed to parse degree: {}", e))?,
+                    size: parts[2]
+                        .parse()
+                        .map_err(|e| format!("Failed to parse size: {}", e))?,
+                    data: decode(parts[3])?,
+                })
+            }
+            FrameType::Parity => {
+                let parts: Vec<&str> = data.split(',').collect();
+                if parts.len() != 6 {
+                    return Err(format!("Expected 6 parts, got {}", parts.len()));
+                }
+
+                let numbers = parts[..5]
+                    .iter()
+                    .map(|part| part.parse::<usize>())
+                    .collect::<Result<Vec<usize>, _>>()
+                    .map_err(|e| format!("Failed to parse parity header: {}", e))?;
+
+                Ok(Message::Parity {
+                    size: numbers[0],
+                    groups: numbers[1],
+                    group: numbers[2],
+                    total: numbers[3],
+                    index: numbers[4],
+                    data: decode(parts[5])?,
+                })
+            }
+            FrameType::Calibration => {
+                let parts: Vec<&str> = data.split(',').collect();
+                if parts.len() != 4 {
+                    return Err(format!("Expected 4 parts, got {}", parts.len()));
+                }
+
+                let numbers = parts[..3]
+                    .iter()
+                    .map(|part| part.parse::<usize>())
+                    .collect::<Result<Vec<usize>, _>>()
+                    .map_err(|e| format!("Failed to parse calibration header: {}", e))?;
+
+                Ok(Message::Calibration {
+                    version: numbers[0],
+                    index: numbers[1],
+                    total: numbers[2],
+                    data: decode(parts[3])?,
+                })
+            }
+            FrameType::Signature => {
+                let (key, signature) = data
+                    .split_once(',')
+                    .ok_or("Signature must contain a ','".to_string())?;
+                Ok(Message::Signature {
+                    key: decode(key)?,
+                    signature: decode(signature)?,
+                })
+            }
+        }
+    }
+
+    /// Frames from senders predating the versioned header, which only sent
+    /// metadata and pieces.
+    fn from_legacy(s: &str) -> Result<Self, String> {
+        if let Some(data) = s.strip_prefix("METADATA:") {
+            Message::from_body(FrameType::Metadata, data)
         } else {
             let parts: Vec<&str> = s.split(':').collect();
             if parts.len() < 2 {
@@ -81,20 +788,174 @@ impl FromStr for Message {
 
             Ok(Message::Piece {
                 index,
-                data: parts[1].to_string(),
+                data: BASE64_STANDARD
+                    .decode(parts[1])
+                    .map_err(|e| format!("Failed to decode data: {}", e))?,
+                checksum: None,
             })
         }
     }
 }
 
+/// A message as it travels in one QR code, tagged with the transfer it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub session: Option<u32>,
+    pub message: Message,
+}
+
+impl Frame {
+    /// A frame of `session`, which is never 0: that stands for no session.
+    pub fn new(session: u32, message: Message) -> Self {
+        assert_ne!(session, 0, "session 0 stands for none");
+        Self {
+            session: Some(session),
+            message,
+        }
+    }
+
+    pub fn encode(&self, encoding: Encoding) -> Result<Vec<u8>, String> {
+        Ok(match encoding {
+            Encoding::Text => self.to_string().into_bytes(),
+            Encoding::Base45 => match self.message.base45_body() {
+                Some(body) => {
+                    let token = format!("{}45", self.message.frame_type().code());
+                    text_frame(self.session, &token, &body).into_bytes()
+                }
+                None => self.to_string().into_bytes(),
+            },
+            Encoding::Binary => self.to_bytes()?,
+        })
+    }
+
+    /// `QRT`, the version byte, the session (0 for none), the frame type code
+    /// and the binary body. The version byte is never `:`, which tells binary
+    /// frames apart from text ones.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = MAGIC.as_bytes().to_vec();
+        bytes.push(VERSION as u8);
+        bytes.extend_from_slice(&self.session.unwrap_or(0).to_be_bytes());
+        bytes.push(self.message.frame_type().code() as u8);
+        bytes.extend(self.message.binary_body()?);
+        Ok(bytes)
+    }
+
+    /// Decodes a QR code payload, binary or text.
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let Some(frame) = bytes
+            .strip_prefix(MAGIC.as_bytes())
+            .filter(|rest| rest.first().is_some_and(|&b| b != b':'))
+        else {
+            let s = std::str::from_utf8(bytes)
+                .map_err(|e| format!("Frame is neither binary nor text: {}", e))?;
+            return Frame::from_str(s);
+        };
+
+        let version = frame[0];
+        if version as u32 != VERSION {
+            return Err(format!(
+                "Unsupported binary protocol version {}, this receiver understands {}",
+                version, VERSION
+            ));
+        }
+        let (session, frame) = frame[1..]
+            .split_first_chunk::<4>()
+            .ok_or("Frame is truncated".to_string())?;
+        let (&frame_type, body) = frame
+            .split_first()
+            .ok_or("Frame is truncated".to_string())?;
+        let frame_type = FrameType::from_str(&(frame_type as char).to_string())?;
+
+        let session = u32::from_be_bytes(*session);
+        Ok(Frame {
+            session: (session != 0).then_some(session),
+            message: Message::from_binary_body(frame_type, body)?,
+        })
+    }
+}
+
+fn text_frame(session: Option<u32>, token: &str, body: &str) -> String {
+    match session {
+        Some(session) => format!("{}:{}:{:08X}:{}:{}", MAGIC, VERSION, session, token, body),
+        None => format!("{}:1:{}:{}", MAGIC, token, body),
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = self.message.frame_type().code().to_string();
+        f.write_str(&text_frame(self.session, &code, &self.message.body()))
+    }
+}
+
+impl FromStr for Frame {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(frame) = s
+            .strip_prefix(MAGIC)
+            .and_then(|rest| rest.strip_prefix(':'))
+        else {
+            return Ok(Frame {
+                session: None,
+                message: Message::from_legacy(s)?,
+            });
+        };
+
+        let (version, frame) = frame.split_once(':').unwrap_or((frame, ""));
+        let version = version
+            .parse::<u32>()
+            .map_err(|e| format!("Failed to parse protocol version: {}", e))?;
+        let (session, frame) = match version {
+            1 => (None, frame),
+            2 => {
+                let (session, frame) = frame.split_once(':').unwrap_or((frame, ""));
+                let session = u32::from_str_radix(session, 16)
+                    .map_err(|e| format!("Failed to parse session: {}", e))?;
+                // as in binary frames
+                ((session != 0).then_some(session), frame)
+            }
+            _ => {
+                return Err(format!(
+                    "Unsupported protocol version {}, this receiver understands up to {}",
+                    version, VERSION
+                ))
+            }
+        };
+
+        let (token, data) = frame.split_once(':').unwrap_or((frame, ""));
+        let message = match token.strip_suffix("45") {
+            Some(code) => Message::from_base45_body(FrameType::from_str(code)?, data)?,
+            None => Message::from_body(FrameType::from_str(token)?, data)?,
+        };
+        Ok(Frame { session, message })
+    }
+}
+
+/// A message without a session, in the version 1 format.
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = self.frame_type().code().to_string();
+        f.write_str(&text_frame(None, &code, &self.body()))
+    }
+}
+
+impl FromStr for Message {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Frame::from_str(s).map(|frame| frame.message)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Payload {
     pub metadata: Metadata,
-    pub pieces: Vec<(usize, String)>,
+    pub pieces: Vec<(usize, Vec<u8>)>,
 }
 
 impl Payload {
-    pub fn new(metadata: Metadata, pieces: Vec<(usize, String)>) -> Self {
+    pub fn new(metadata: Metadata, pieces: Vec<(usize, Vec<u8>)>) -> Self {
         Self { metadata, pieces }
     }
 
@@ -124,6 +985,55 @@ mod tests {
         assert_eq!(metadata.name, "test_name");
         assert_eq!(metadata.length, 42);
         assert_eq!(metadata.hash, "abc123");
+        assert_eq!(metadata.hash_algorithm, HashAlgorithm::Sha1);
+        assert!(!metadata.encrypted);
+    }
+
+    #[test]
+    fn test_metadata_options() {
+        let metadata = Metadata {
+            encrypted: true,
+            hash_algorithm: HashAlgorithm::Blake3,
+            codec: Codec::Zstd,
+            size: Some(4200),
+            info: FileInfo {
+                mtime: Some(1_700_000_000),
+                mode: Some(0o755),
+                mime: Some("image/svg+xml".to_string()),
+            },
+            ..Metadata::new("test_name".to_string(), 42, "abc123".to_string())
+        };
+        let s = metadata.to_string();
+        assert_eq!(
+            s,
+            "METADATA:test_name,42,abc123,c=zstd,enc=scrypt-chacha20poly1305,h=blake3,\
+             mime=image/svg+xml,mode=755,s=4200,t=1700000000"
+        );
+        assert_eq!(Metadata::from_str(&s).unwrap(), metadata);
+
+        // unknown options are kept for newer receivers, values may be any text
+        let s = "METADATA:test_name,42,abc123,future=1,note=50%25 off%2C today";
+        let metadata = Metadata::from_str(s).unwrap();
+        assert!(!metadata.encrypted);
+        assert_eq!(metadata.extra.get("future"), Some("1"));
+        assert_eq!(metadata.extra.get("note"), Some("50% off, today"));
+        assert_eq!(metadata.to_string(), s);
+        assert!(Metadata::from_str("METADATA:test_name,42,abc123,s=1,s=2").is_err());
+        assert!(Metadata::from_str("METADATA:test_name,42,abc123,Key=1").is_err());
+        assert!(Metadata::from_str("METADATA:test_name,42,abc123,note=%2").is_err());
+        assert!(Metadata::from_str("METADATA:test_name,42,abc123,enc=rot13").is_err());
+        assert!(Metadata::from_str("METADATA:test_name,42,abc123,h=md5").is_err());
+        assert!(Metadata::from_str("METADATA:test_name,42,abc123,c=lzma").is_err());
+        assert!(Metadata::from_str("METADATA:test_name,42,abc123,s=-1").is_err());
+        assert!(Metadata::from_str("METADATA:test_name,42,abc123,mode=9").is_err());
+        assert!(Metadata::from_str("METADATA:test_name,42,abc123,mime=text").is_err());
+
+        assert_eq!(
+            mime_essence("Text/Plain; charset=utf-8").as_deref(),
+            Some("text/plain")
+        );
+        assert_eq!(mime_essence(""), None);
+        assert_eq!(mime_essence("a/b/c"), None);
     }
 
     #[test]
@@ -140,11 +1050,211 @@ mod tests {
 
     #[test]
     fn test_message_piece_roundtrip() {
+        let msg = Message::piece(5, b"somedata".to_vec());
+        let s = msg.to_string();
+        let parsed = Message::from_str(&s).unwrap();
+        assert_eq!(msg, parsed);
+        assert!(parsed.is_intact());
+    }
+
+    #[test]
+    fn test_message_piece_checksum() {
+        let Message::Piece { checksum, .. } = Message::piece(5, b"somedata".to_vec()) else {
+            unreachable!()
+        };
+        let misread = Message::Piece {
+            index: 5,
+            data: b"s0medata".to_vec(),
+            checksum,
+        };
+        assert!(!misread.is_intact());
+        // older senders send no checksum, nothing to check
+        assert!(Message::from_str("QRT:1:P:5,c29tZWRhdGE=")
+            .unwrap()
+            .is_intact());
+        assert!(Message::from_str("QRT:1:P:5,c29tZWRhdGE=,xyz").is_err());
+    }
+
+    #[test]
+    fn test_message_header() {
         let msg = Message::Piece {
             index: 5,
-            data: "somedata".to_string(),
+            data: b"somedata".to_vec(),
+            checksum: None,
+        };
+        assert_eq!(msg.to_string(), "QRT:1:P:5,c29tZWRhdGE=");
+
+        let msg = Message::Metadata(Metadata::new(
+            "filename".to_string(),
+            10,
+            "hash123".to_string(),
+        ));
+        assert_eq!(msg.to_string(), "QRT:1:M:filename,10,hash123");
+    }
+
+    #[test]
+    fn test_message_legacy() {
+        assert_eq!(
+            Message::from_str("METADATA:filename,10,hash123").unwrap(),
+            Message::Metadata(Metadata::new(
+                "filename".to_string(),
+                10,
+                "hash123".to_string(),
+            ))
+        );
+        assert_eq!(
+            Message::from_str("5:c29tZWRhdGE=").unwrap(),
+            Message::Piece {
+                index: 5,
+                data: b"somedata".to_vec(),
+                checksum: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_message_unsupported_version() {
+        let err = Message::from_str("QRT:3:0000ABCD:P:5,somedata").unwrap_err();
+        assert_eq!(
+            err,
+            "Unsupported protocol version 3, this receiver understands up to 2"
+        );
+        assert!(Message::from_str("QRT:1:X:5,somedata").is_err());
+    }
+
+    #[test]
+    fn test_frame_session() {
+        let frame = Frame::new(
+            0xABCD,
+            Message::Piece {
+                index: 5,
+                data: b"somedata".to_vec(),
+                checksum: None,
+            },
+        );
+        let s = frame.to_string();
+        assert_eq!(s, "QRT:2:0000ABCD:P:5,c29tZWRhdGE=");
+        assert_eq!(Frame::from_str(&s).unwrap(), frame);
+
+        let v1 = Frame::from_str("QRT:1:P:5,c29tZWRhdGE=").unwrap();
+        assert_eq!(v1.session, None);
+        assert_eq!(v1.message, frame.message);
+        assert!(Frame::from_str("QRT:2:P:5,c29tZWRhdGE=").is_err());
+    }
+
+    #[test]
+    fn test_frame_binary_roundtrip() {
+        let messages = vec![
+            Message::Metadata(Metadata::new(
+                "filename".to_string(),
+                10,
+                "hash123".to_string(),
+            )),
+            Message::piece(5, vec![0, 58, 255, 1]),
+            Message::Symbol {
+                seed: 7,
+                degree: 3,
+                size: 1234,
+                data: vec![1, 2, 3],
+            },
+            Message::Parity {
+                size: 1234,
+                groups: 2,
+                group: 1,
+                total: 3,
+                index: 0,
+                data: vec![4, 5, 6],
+            },
+            Message::Calibration {
+                version: 10,
+                index: 2,
+                total: 3,
+                data: vec![7, 8, 9],
+            },
+            Message::Signature {
+                key: vec![1; 32],
+                signature: vec![2; 64],
+            },
+        ];
+        for message in messages {
+            let frame = Frame::new(0xABCD, message);
+            let bytes = frame.encode(Encoding::Binary).unwrap();
+            assert_eq!(&bytes[..8], b"QRT\x02\x00\x00\xAB\xCD");
+            assert_eq!(Frame::decode(&bytes).unwrap(), frame);
+
+            let text = frame.encode(Encoding::Text).unwrap();
+            assert_eq!(Frame::decode(&text).unwrap(), frame);
+
+            let base45 = frame.encode(Encoding::Base45).unwrap();
+            assert_eq!(Frame::decode(&base45).unwrap(), frame);
+        }
+
+        assert!(Frame::decode(b"QRT\x02\x00\x00\xAB\xCDP\x00").is_err());
+
+        // session 0 is no session in every form
+        let sessionless = Frame {
+            session: None,
+            message: Message::piece(5, vec![1, 2]),
+        };
+        let zero = Frame::from_str("QRT:2:00000000:P:5,AQI=").unwrap();
+        assert_eq!(zero.session, None);
+        let bytes = sessionless.encode(Encoding::Binary).unwrap();
+        assert_eq!(Frame::decode(&bytes).unwrap().session, None);
+
+        // fields too large for a binary frame aren't cut short
+        let huge = Frame::new(
+            0xABCD,
+            Message::Parity {
+                size: MAX_FIELD + 1,
+                groups: 1,
+                group: 0,
+                total: 1,
+                index: 0,
+                data: vec![0],
+            },
+        );
+        assert!(huge.encode(Encoding::Binary).is_err());
+        assert!(huge.encode(Encoding::Text).is_ok());
+        assert!(Frame::from_str("QRT:2:0000ABCD:S45:4294967296:1:10:").is_err());
+    }
+
+    #[test]
+    fn test_frame_base45() {
+        let frame = Frame::new(0xABCD, Message::piece(5, b"somedata".to_vec()));
+        let base45 = String::from_utf8(frame.encode(Encoding::Base45).unwrap()).unwrap();
+        assert_eq!(base45, "QRT:2:0000ABCD:P45:5:1393343952:VQEF$D2VC3WE");
+        assert!(base45
+            .chars()
+            .all(|c| "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:".contains(c)));
+        assert!(Frame::from_str("QRT:2:0000ABCD:M45:name,1,hash").is_err());
+    }
+
+    #[test]
+    fn test_message_symbol_roundtrip() {
+        let msg = Message::Symbol {
+            seed: 7,
+            degree: 3,
+            size: 1234,
+            data: b"somedata".to_vec(),
+        };
+        let s = msg.to_string();
+        assert_eq!(s, "QRT:1:S:7,3,1234,c29tZWRhdGE=");
+        let parsed = Message::from_str(&s).unwrap();
+        assert_eq!(msg, parsed);
+    }
+
+    #[test]
+    fn test_message_parity_roundtrip() {
+        let msg = Message::Parity {
+            size: 1234,
+            groups: 2,
+            group: 1,
+            total: 3,
+            index: 0,
+            data: b"somedata".to_vec(),
         };
         let s = msg.to_string();
+        assert_eq!(s, "QRT:1:R:1234,2,1,3,0,c29tZWRhdGE=");
         let parsed = Message::from_str(&s).unwrap();
         assert_eq!(msg, parsed);
     }
@@ -152,7 +1262,7 @@ mod tests {
     #[test]
     fn test_payload_creation() {
         let metadata = Metadata::new("testfile".to_string(), 2, "hash123".to_string());
-        let pieces = vec![(1, "data1".to_string()), (2, "data2".to_string())];
+        let pieces = vec![(1, b"data1".to_vec()), (2, b"data2".to_vec())];
         let payload = Payload::new(metadata.clone(), pieces.clone());
 
         assert_eq!(payload.metadata, metadata);
@@ -1,63 +1,211 @@
 #![allow(non_snake_case)]
 
-use crate::compress::decompress;
+use super::{DecodeError, Progress, State};
+use crate::bundle::{self, Entry};
+use crate::calibration::Calibration;
+use crate::crypto;
+use crate::filename;
+use crate::fountain::FountainDecoder;
+use crate::parity::{self, ParityDecoder};
+use crate::protocol::FileInfo;
+use crate::protocol::Frame;
 use crate::protocol::Message;
-use crate::protocol::Metadata;
+use crate::protocol::{Metadata, MIN_CHUNK_SIZE};
+use crate::sign::{self, Verification, VerifyingKey};
 use crate::utils::hash;
-use crate::utils::log;
+use crate::utils::{log, now};
 use base64::{prelude::BASE64_STANDARD, Engine as _};
-use image::{DynamicImage, ImageBuffer, RgbaImage};
+use image::{DynamicImage, GrayImage, ImageBuffer, RgbaImage};
+use indexmap::IndexMap;
 use quircs::Quirc;
-use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::mem::take;
-use std::str::FromStr;
 use wasm_bindgen::prelude::*;
 
 #[derive(Default)]
 struct Initted {
     received_msgs: HashSet<Message>,
     metadata: Option<Metadata>,
+    corrupted: usize,
 }
 
 #[derive(Default)]
 struct Started {
-    expected_iterations: HashSet<String>,
     received_msgs: HashSet<Message>,
+    /// Indices of the pieces received or recovered, within the announced count.
+    pieces: HashSet<usize>,
     metadata: Metadata,
+    fountain: Option<FountainDecoder>,
+    parity: Option<ParityDecoder>,
+    /// Bytes in every piece but the last, once a piece or repair frame showed it.
+    chunk: Option<usize>,
+    /// Pieces discarded for not matching their checksum.
+    corrupted: usize,
 }
 
 #[wasm_bindgen]
 pub struct Finished {
     metadata: Metadata,
     data: Vec<u8>,
+    /// `data` opened by `unlock`.
+    decrypted: Option<Vec<u8>>,
+    /// Public keys and signatures from the signature frames.
+    signatures: Vec<(Vec<u8>, Vec<u8>)>,
+    trusted_keys: Vec<VerifyingKey>,
+    max_size: usize,
+    /// Indices that came with conflicting data, when no choice among them
+    /// matched the hash.
+    ambiguous: Vec<usize>,
 }
 
 #[wasm_bindgen]
 impl Finished {
-    fn get_decompressed_data(&self) -> Vec<u8> {
+    fn get_decompressed_data(&self) -> Result<Vec<u8>, DecodeError> {
+        let data = match (self.metadata.encrypted, &self.decrypted) {
+            (false, _) => self.data.clone(),
+            (true, Some(decrypted)) => decrypted.clone(),
+            (true, None) => return Err(DecodeError::PassphraseRequired),
+        };
         log("Decompressing...");
-        decompress(self.data.clone())
+        let limit = self
+            .metadata
+            .size
+            .unwrap_or(self.max_size)
+            .min(self.max_size);
+        let data = self.metadata.codec.decompress(data, limit)?;
+        match self.metadata.size {
+            Some(size) if size != data.len() => Err(DecodeError::Decompression(format!(
+                "expected {} bytes, got {}",
+                size,
+                data.len()
+            ))),
+            _ => Ok(data),
+        }
     }
 
-    fn check_integrity(&self) {
-        let final_hash = hash(&self.data);
+    fn check_integrity(&self) -> Result<(), DecodeError> {
+        let final_hash = self.metadata.hash_algorithm.digest(&self.data);
 
         let received_hash = &self.metadata.hash;
+        if received_hash != &final_hash && !self.ambiguous.is_empty() {
+            return Err(DecodeError::AmbiguousPieces(self.ambiguous.clone()));
+        }
         if received_hash != &final_hash {
-            panic!("[*] Expected: {}, got: {}", received_hash, final_hash)
+            return Err(DecodeError::HashMismatch {
+                expected: received_hash.clone(),
+                actual: final_hash,
+            });
         }
+        Ok(())
+    }
+
+    /// The received file, checked and decompressed.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DecodeError> {
+        self.check_integrity()?;
+        self.get_decompressed_data()
+    }
+
+    pub fn to_base64(&self) -> Result<String, DecodeError> {
+        Ok(BASE64_STANDARD.encode(self.to_bytes()?))
+    }
+
+    /// The name as the sender gave it, only fit for display.
+    pub fn get_name(&self) -> Result<String, DecodeError> {
+        decode_name(&self.metadata.name)
+    }
+
+    /// The name to save the file under, see `filename::sanitize`.
+    pub fn get_safe_name(&self) -> Result<String, DecodeError> {
+        Ok(filename::sanitize(&self.get_name()?))
+    }
+
+    /// The MIME type the sender declared, or else one guessed from the name.
+    pub fn mime_type(&self) -> String {
+        match &self.metadata.info.mime {
+            Some(mime) => mime.clone(),
+            None => mime_guess::from_path(self.get_safe_name().unwrap_or_default())
+                .first_or_octet_stream()
+                .to_string(),
+        }
+    }
+
+    /// Whether `unlock` must be called before reading the file.
+    pub fn is_encrypted(&self) -> bool {
+        self.metadata.encrypted
+    }
+
+    /// Who signed the transfer, as shown next to the download.
+    #[wasm_bindgen(js_name = verification)]
+    pub fn verification_text(&self) -> String {
+        self.verification().to_string()
+    }
+
+    /// Decrypts the file, failing with `Decryption` for a wrong passphrase.
+    pub fn unlock(&mut self, passphrase: &str) -> Result<(), DecodeError> {
+        self.check_integrity()?;
+        self.decrypted = Some(crypto::decrypt(&self.data, passphrase)?);
+        Ok(())
+    }
+
+    /// Whether several files were sent, see `files`.
+    pub fn is_bundle(&self) -> bool {
+        self.get_name().is_ok_and(|name| bundle::is_bundle(&name))
     }
 
-    pub fn to_base64(&self) -> String {
-        self.check_integrity();
-        let decompressed_data = self.get_decompressed_data();
-        BASE64_STANDARD.encode(decompressed_data)
+    /// All the files of a multi-file transfer in one tar archive.
+    pub fn to_tar(&self) -> Result<Vec<u8>, DecodeError> {
+        Ok(bundle::tar(&self.files()?))
     }
+}
 
-    pub fn get_name(&self) -> String {
-        let decoded_bytes = BASE64_STANDARD.decode(&self.metadata.name).unwrap();
-        String::from_utf8(decoded_bytes).unwrap_or_else(|_| self.metadata.name.clone())
+fn session_name(session: Option<u32>) -> String {
+    match session {
+        Some(session) => format!("session {:08X}", session),
+        None => "frames without a session".to_string(),
+    }
+}
+
+fn decode_name(name: &str) -> Result<String, DecodeError> {
+    let decoded_bytes = BASE64_STANDARD
+        .decode(name)
+        .map_err(|e| DecodeError::BadBase64(e.to_string()))?;
+    Ok(String::from_utf8(decoded_bytes).unwrap_or_else(|_| name.to_string()))
+}
+
+impl Finished {
+    /// Modification time, permissions and MIME type, as far as the sender
+    /// sent them.
+    pub fn info(&self) -> &FileInfo {
+        &self.metadata.info
+    }
+
+    /// The best of the signatures received, so a bogus signature frame shown
+    /// alongside can't hide a good one.
+    pub fn verification(&self) -> Verification {
+        if !self.metadata.signed {
+            return Verification::Unsigned;
+        }
+        self.signatures
+            .iter()
+            .map(|(key, signature)| {
+                sign::verify(&self.metadata, key, signature, &self.trusted_keys)
+            })
+            .max()
+            .unwrap_or(Verification::Invalid)
+    }
+
+    /// The files of a multi-file transfer, or the single file sent.
+    pub fn files(&self) -> Result<Vec<(Entry, Vec<u8>)>, DecodeError> {
+        let data = self.to_bytes()?;
+        if self.is_bundle() {
+            return bundle::unpack(&data);
+        }
+        let entry = Entry {
+            path: self.get_name()?,
+            size: data.len(),
+            hash: hash(&data),
+        };
+        Ok(vec![(entry, data)])
     }
 }
 
@@ -86,141 +234,545 @@ impl From<Machine<Initted>> for Machine<Started> {
     fn from(machine: Machine<Initted>) -> Machine<Started> {
         let length = machine.state.metadata.as_ref().unwrap().length;
         log(&format!("[*] The message will come in {} parts", length));
-        Machine {
+        let mut started = Machine {
             state: Started {
-                expected_iterations: {
-                    let mut iterations = HashSet::new();
-                    iterations.insert("METADATA".to_string());
-                    for i in 1..=length {
-                        iterations.insert(i.to_string());
-                    }
-                    iterations
-                },
-                received_msgs: machine.state.received_msgs,
+                received_msgs: HashSet::new(),
+                pieces: HashSet::new(),
                 metadata: machine.state.metadata.unwrap(),
+                fountain: None,
+                parity: None,
+                chunk: None,
+                corrupted: machine.state.corrupted,
             },
+        };
+        // pieces first, they tell the chunk length repair frames must have
+        let (pieces, others): (Vec<Message>, Vec<Message>) = machine
+            .state
+            .received_msgs
+            .into_iter()
+            .partition(|msg| matches!(msg, Message::Piece { .. }));
+        for msg in pieces.into_iter().chain(others) {
+            if let Message::Piece { index, data, .. } = &msg {
+                started.note_chunk(*index, data.len());
+            }
+            if started.accepts(&msg) {
+                started.keep(msg.clone());
+                started.recover(&msg);
+            }
         }
+        started
     }
 }
 
 impl From<Machine<Started>> for Machine<Finished> {
     fn from(machine: Machine<Started>) -> Machine<Finished> {
-        assert!(machine.check_finished(), "Incomplete data.");
+        debug_assert!(machine.check_finished(), "Incomplete data.");
+        let length = machine.state.metadata.length;
+        let signatures = machine
+            .state
+            .received_msgs
+            .iter()
+            .filter_map(|msg| match msg {
+                Message::Signature { key, signature } => Some((key.clone(), signature.clone())),
+                _ => None,
+            })
+            .collect();
+        let mut candidates = vec![Vec::new(); length];
+        for msg in machine.state.received_msgs {
+            match msg {
+                Message::Piece { index, data, .. } if (1..=length).contains(&index) => {
+                    candidates[index - 1].push(data)
+                }
+                _ => {}
+            }
+        }
+        for datas in &mut candidates {
+            datas.sort();
+        }
+        let (data, ambiguous) = assemble(&machine.state.metadata, &candidates);
         Machine {
             state: Finished {
                 metadata: machine.state.metadata,
-                data: {
-                    let mut ordered_iteration = machine
-                        .state
-                        .received_msgs
-                        .into_iter()
-                        .filter(|msg| matches!(msg, Message::Piece { .. }))
-                        .collect::<Vec<Message>>();
-                    ordered_iteration.sort_by(|x, y| {
-                        if let (
-                            Message::Piece { index: xi, .. },
-                            Message::Piece { index: yi, .. },
-                        ) = (x, y)
-                        {
-                            xi.cmp(yi)
-                        } else {
-                            panic!("")
-                        }
-                    });
-                    log(&format!("{:?}", ordered_iteration));
-                    let data = ordered_iteration
-                        .iter()
-                        .map(|msg| {
-                            if let Message::Piece { data, .. } = msg {
-                                BASE64_STANDARD.decode(data).unwrap()
-                            } else {
-                                panic!("")
-                            }
-                        })
-                        .collect::<Vec<Vec<u8>>>()
-                        .concat();
-                    data
-                },
+                data,
+                decrypted: None,
+                signatures,
+                trusted_keys: Vec::new(),
+                max_size: DEFAULT_MAX_SIZE,
+                ambiguous,
             },
         }
     }
 }
 
-impl Machine<Started> {
-    fn expecting(&self) -> HashSet<&String> {
-        let received_iterations = self
-            .state
-            .received_msgs
+/// Largest file a receiver accepts unless told otherwise, 100 MiB take about
+/// a day to scan.
+pub const DEFAULT_MAX_SIZE: usize = 100 << 20;
+
+/// Frames held for sessions whose metadata did not come yet, all of them
+/// together, at most a few megabytes of pieces.
+const MAX_EARLY_FRAMES: usize = 4096;
+
+/// Sessions tracked at once, received or refused. Every frame names its own
+/// session, so a noisy screen could otherwise make up new ones forever.
+const MAX_SESSIONS: usize = 16;
+
+/// Combinations of conflicting pieces tried against the hash, beyond that
+/// the conflicting indices are reported instead.
+const MAX_COMBINATIONS: usize = 64;
+
+/// Joins the pieces, each index having one or more candidate datas. Where two
+/// senders or a misread code left several, the combination matching the hash
+/// wins. Also returns the conflicting indices if none does.
+fn assemble(metadata: &Metadata, candidates: &[Vec<Vec<u8>>]) -> (Vec<u8>, Vec<usize>) {
+    let conflicts = (0..candidates.len())
+        .filter(|&i| candidates[i].len() > 1)
+        .collect::<Vec<usize>>();
+    let join = |choice: &[usize]| {
+        let mut chosen = vec![0; candidates.len()];
+        for (&i, &j) in conflicts.iter().zip(choice) {
+            chosen[i] = j;
+        }
+        candidates
             .iter()
-            .map(|it| match it {
-                Message::Metadata(_) => "METADATA".to_string(),
-                Message::Piece { index, .. } => index.to_string(),
-            })
-            .collect::<HashSet<String>>();
+            .zip(chosen)
+            .flat_map(|(datas, j)| datas[j].iter().copied())
+            .collect::<Vec<u8>>()
+    };
+    if conflicts.is_empty() {
+        return (join(&[]), Vec::new());
+    }
+
+    let mut choice = vec![0; conflicts.len()];
+    for _ in 0..MAX_COMBINATIONS {
+        let data = join(&choice);
+        if metadata.hash_algorithm.digest(&data) == metadata.hash {
+            log(&format!("[*] Resolved conflicting pieces {:?}", conflicts));
+            return (data, Vec::new());
+        }
+        // next combination, counting with one digit per conflicting index
+        let Some(k) =
+            (0..conflicts.len()).find(|&k| choice[k] + 1 < candidates[conflicts[k]].len())
+        else {
+            break;
+        };
+        choice[k] += 1;
+        choice[..k].fill(0);
+    }
+    let ambiguous = conflicts.iter().map(|i| i + 1).collect();
+    log(&format!("[!] Conflicting pieces {:?}", ambiguous));
+    (join(&vec![0; conflicts.len()]), ambiguous)
+}
+
+impl Machine<Started> {
+    /// Indices of the pieces still missing, in order.
+    fn missing(&self) -> Vec<usize> {
+        (1..=self.state.metadata.length)
+            .filter(|index| !self.state.pieces.contains(index))
+            .collect()
+    }
+
+    fn signature_pending(&self) -> bool {
+        self.state.metadata.signed
+            && !self
+                .state
+                .received_msgs
+                .iter()
+                .any(|msg| matches!(msg, Message::Signature { .. }))
+    }
 
+    fn check_finished(&self) -> bool {
+        self.state.pieces.len() == self.state.metadata.length && !self.signature_pending()
+    }
+
+    fn pieces(&self) -> Vec<(usize, Vec<u8>)> {
         self.state
-            .expected_iterations
+            .received_msgs
             .iter()
-            .filter(|s| !received_iterations.contains(*s))
+            .filter_map(|msg| match msg {
+                Message::Piece { index, data, .. } => Some((index.checked_sub(1)?, data.clone())),
+                _ => None,
+            })
             .collect()
     }
 
-    fn check_finished(&self) -> bool {
-        self.expecting().is_empty()
+    /// Takes the chunk length from a piece that is not the last one.
+    fn note_chunk(&mut self, index: usize, len: usize) {
+        if self.state.chunk.is_none() && (1..self.state.metadata.length).contains(&index) {
+            self.state.chunk = Some(len);
+        }
     }
-}
 
-trait Receive {
-    fn get_mut_metadata(&mut self) -> &mut Option<Metadata>;
-    fn set_metadata(&mut self, metadata: Metadata) {
-        log(&format!("[*] Metadata: {}", metadata));
-        *(self.get_mut_metadata()) = Some(metadata)
+    /// Whether a repair frame of `size` bytes in blocks of `block_size`
+    /// describes the announced transfer, so a forged or foreign frame can't
+    /// size the decoders.
+    fn fits(&self, size: usize, block_size: usize) -> bool {
+        block_size > 0
+            && size.div_ceil(block_size) == self.state.metadata.length
+            && self.state.chunk.is_none_or(|chunk| chunk == block_size)
     }
-    fn get_mut_received_msgs(&mut self) -> &mut HashSet<Message>;
-    fn update(&mut self, msg: Message) -> bool {
-        let received_msgs = self.get_mut_received_msgs();
 
-        if received_msgs.contains(&msg) {
-            return false;
+    /// Whether a frame is consistent with the metadata and the frames before
+    /// it: pieces within the announced count, repair frames of its size. Those
+    /// that are not are dropped without building a decoder.
+    fn accepts(&self, msg: &Message) -> bool {
+        match msg {
+            Message::Symbol {
+                degree, size, data, ..
+            } => {
+                self.fits(*size, data.len())
+                    && (1..=self.state.metadata.length).contains(degree)
+                    && self.state.fountain.as_ref().is_none_or(|fountain| {
+                        fountain.size() == *size && fountain.block_size() == data.len()
+                    })
+            }
+            Message::Parity {
+                size,
+                groups,
+                group,
+                total,
+                index,
+                data,
+            } => {
+                self.fits(*size, data.len())
+                    && parity::is_layout(self.state.metadata.length, *groups, *group, *total)
+                    && index < total
+                    && self.state.parity.as_ref().is_none_or(|parity| {
+                        parity.size() == *size
+                            && parity.block_size() == data.len()
+                            && parity.groups() == *groups
+                    })
+            }
+            Message::Piece { index, .. } => (1..=self.state.metadata.length).contains(index),
+            _ => true,
         }
+    }
 
-        received_msgs.insert(msg.clone());
+    fn start_fountain(&self, size: usize, block_size: usize) -> FountainDecoder {
+        let mut fountain = FountainDecoder::new(size, block_size);
+        for (i, data) in self.pieces() {
+            fountain.add_block(i, &data);
+        }
+        fountain
+    }
 
-        if let Message::Metadata(metadata) = msg.clone() {
-            self.set_metadata(metadata);
+    fn start_parity(&self, size: usize, block_size: usize, groups: usize) -> ParityDecoder {
+        let mut parity = ParityDecoder::new(size, block_size, groups);
+        for (i, data) in self.pieces() {
+            parity.add_block(i, &data);
         }
+        parity
+    }
+
+    /// Let fountain symbols and parity pieces fill in missing pieces.
+    fn recover(&mut self, msg: &Message) {
+        let mut queue = vec![msg.clone()];
+        while let Some(msg) = queue.pop() {
+            let recovered = match msg {
+                Message::Symbol {
+                    seed,
+                    degree,
+                    size,
+                    data,
+                } => {
+                    if self.state.fountain.is_none() {
+                        self.state.fountain = Some(self.start_fountain(size, data.len()));
+                        self.state.chunk = Some(data.len());
+                    }
+                    let fountain = self.state.fountain.as_mut().unwrap();
+                    let recovered = fountain.add_symbol(seed, degree, &data);
+                    recovered
+                        .into_iter()
+                        .map(|i| (i, fountain.block(i).unwrap().to_vec()))
+                        .collect()
+                }
+                Message::Parity {
+                    size,
+                    groups,
+                    group,
+                    total,
+                    index,
+                    data,
+                } => {
+                    if self.state.parity.is_none() {
+                        self.state.parity = Some(self.start_parity(size, data.len(), groups));
+                        self.state.chunk = Some(data.len());
+                    }
+                    let parity = self.state.parity.as_mut().unwrap();
+                    let recovered = parity.add_parity(group, total, index, &data);
+                    recovered
+                        .into_iter()
+                        .map(|i| (i, parity.block(i).unwrap().to_vec()))
+                        .collect()
+                }
+                Message::Piece { index, data, .. } => {
+                    let Some(i) = index.checked_sub(1) else {
+                        continue;
+                    };
+                    let mut recovered = Vec::new();
+                    if let Some(fountain) = &mut self.state.fountain {
+                        for j in fountain.add_block(i, &data) {
+                            recovered.push((j, fountain.block(j).unwrap().to_vec()));
+                        }
+                    }
+                    if let Some(parity) = &mut self.state.parity {
+                        for j in parity.add_block(i, &data) {
+                            recovered.push((j, parity.block(j).unwrap().to_vec()));
+                        }
+                    }
+                    recovered
+                }
+                Message::Metadata(_) | Message::Calibration { .. } | Message::Signature { .. } => {
+                    continue
+                }
+            };
+
+            for (i, data) in recovered {
+                let piece = Message::Piece {
+                    index: i + 1,
+                    data,
+                    checksum: None,
+                };
+                if self.keep(piece.clone()) {
+                    log(&format!("[*] Recovered piece {}", i + 1));
+                    queue.push(piece);
+                }
+            }
+        }
+    }
+}
+
+trait Receive {
+    /// Accepts the metadata, unless the session already announced another file.
+    fn set_metadata(&mut self, metadata: Metadata) -> Result<(), DecodeError>;
+    fn get_mut_received_msgs(&mut self) -> &mut HashSet<Message>;
+    /// Stores a new frame, returning whether it was new.
+    fn keep(&mut self, msg: Message) -> bool;
+    fn get_mut_corrupted(&mut self) -> &mut usize;
+    /// Discards pieces misread by the camera, a later good copy takes their place.
+    fn update(&mut self, msg: Message) -> Result<bool, DecodeError> {
+        if !msg.is_intact() {
+            log(&format!("[!] Discarded corrupted piece {}", msg.label()));
+            *self.get_mut_corrupted() += 1;
+            return Ok(false);
+        }
+        // kept without the checksum, like the pieces recovered from repair codes
+        let msg = match msg {
+            Message::Piece { index, data, .. } => Message::Piece {
+                index,
+                data,
+                checksum: None,
+            },
+            msg => msg,
+        };
+        if self.get_mut_received_msgs().contains(&msg) {
+            return Ok(false);
+        }
+
+        if let Message::Metadata(metadata) = &msg {
+            self.set_metadata(metadata.clone())?;
+        }
+
+        self.keep(msg);
+        Ok(true)
+    }
+}
 
-        true
+fn check_metadata(expected: &Metadata, received: Metadata) -> Result<(), DecodeError> {
+    if expected != &received {
+        return Err(DecodeError::ConflictingMetadata {
+            expected: Box::new(expected.clone()),
+            received: Box::new(received),
+        });
     }
+    Ok(())
 }
 
 impl Receive for Machine<Initted> {
-    fn get_mut_metadata(&mut self) -> &mut Option<Metadata> {
-        &mut self.state.metadata
+    fn set_metadata(&mut self, metadata: Metadata) -> Result<(), DecodeError> {
+        if let Some(expected) = &self.state.metadata {
+            return check_metadata(expected, metadata);
+        }
+        log(&format!("[*] Metadata: {}", metadata));
+        self.state.metadata = Some(metadata);
+        Ok(())
     }
     fn get_mut_received_msgs(&mut self) -> &mut HashSet<Message> {
         &mut self.state.received_msgs
     }
+    fn keep(&mut self, msg: Message) -> bool {
+        self.state.received_msgs.insert(msg)
+    }
+    fn get_mut_corrupted(&mut self) -> &mut usize {
+        &mut self.state.corrupted
+    }
 }
 impl Receive for Machine<Started> {
-    fn get_mut_metadata(&mut self) -> &mut Option<Metadata> {
-        panic!("Started state already has metadata.")
+    fn set_metadata(&mut self, metadata: Metadata) -> Result<(), DecodeError> {
+        check_metadata(&self.state.metadata, metadata)
     }
     fn get_mut_received_msgs(&mut self) -> &mut HashSet<Message> {
         &mut self.state.received_msgs
     }
+    /// Counts pieces as they come, the metadata says how many to expect.
+    fn keep(&mut self, msg: Message) -> bool {
+        if let Message::Piece { index, .. } = &msg {
+            self.state.pieces.insert(*index);
+        }
+        self.state.received_msgs.insert(msg)
+    }
+    fn get_mut_corrupted(&mut self) -> &mut usize {
+        &mut self.state.corrupted
+    }
+}
+
+fn piece_bytes(received_msgs: &HashSet<Message>) -> usize {
+    received_msgs
+        .iter()
+        .map(|msg| match msg {
+            Message::Piece { data, .. } => data.len(),
+            _ => 0,
+        })
+        .sum()
 }
 
+#[allow(clippy::large_enum_variant)]
 enum MachineWrapper {
     Initted(Machine<Initted>),
     Started(Machine<Started>),
     Finished(Machine<Finished>),
 }
 
+impl Default for MachineWrapper {
+    fn default() -> Self {
+        MachineWrapper::Initted(Machine::default())
+    }
+}
+
+impl MachineWrapper {
+    fn received(&self) -> usize {
+        match self {
+            MachineWrapper::Initted(machine) => machine.state.received_msgs.len(),
+            MachineWrapper::Started(machine) => machine.state.received_msgs.len(),
+            MachineWrapper::Finished(machine) => machine.state.metadata.length + 1,
+        }
+    }
+
+    /// Frames held until the metadata comes.
+    fn early(&self) -> usize {
+        match self {
+            MachineWrapper::Initted(machine) => machine.state.received_msgs.len(),
+            _ => 0,
+        }
+    }
+
+    /// How far along this session is, to pick the one worth reporting.
+    fn rank(&self) -> (u8, usize) {
+        let stage = match self {
+            MachineWrapper::Initted(_) => 0,
+            MachineWrapper::Started(_) => 1,
+            MachineWrapper::Finished(_) => 2,
+        };
+        (stage, self.received())
+    }
+
+    /// Progress of this session alone.
+    fn progress(&self) -> Progress {
+        match self {
+            MachineWrapper::Initted(machine) => Progress {
+                received: machine.state.received_msgs.len(),
+                bytes: piece_bytes(&machine.state.received_msgs),
+                corrupted: machine.state.corrupted,
+                ..Progress::new(State::Waiting)
+            },
+            MachineWrapper::Finished(machine) => Progress {
+                received: machine.state.metadata.length
+                    + 1
+                    + machine.state.metadata.signed as usize,
+                total: Some(
+                    machine.state.metadata.length + 1 + machine.state.metadata.signed as usize,
+                ),
+                bytes: machine.state.data.len(),
+                file_name: machine.state.get_name().ok(),
+                ..Progress::new(State::Finished)
+            },
+            MachineWrapper::Started(machine) => {
+                let missing = machine.missing();
+                let signature_pending = machine.signature_pending();
+                let total =
+                    machine.state.metadata.length + 1 + machine.state.metadata.signed as usize;
+                Progress {
+                    received: total - missing.len() - signature_pending as usize,
+                    total: Some(total),
+                    missing,
+                    signature_pending,
+                    bytes: piece_bytes(&machine.state.received_msgs),
+                    file_name: decode_name(&machine.state.metadata.name).ok(),
+                    pending_symbols: machine
+                        .state
+                        .fountain
+                        .as_ref()
+                        .map_or(0, |fountain| fountain.pending()),
+                    corrupted: machine.state.corrupted,
+                    ..Progress::new(State::Receiving)
+                }
+            }
+        }
+    }
+
+    fn try_evolve(&mut self) {
+        match self {
+            MachineWrapper::Initted(decoder) if decoder.state.metadata.is_some() => {
+                *self = MachineWrapper::Started(take(decoder).into());
+                self.try_evolve(); // may evolve agian
+            }
+            MachineWrapper::Started(decoder) if decoder.check_finished() => {
+                *self = MachineWrapper::Finished(take(decoder).into());
+            }
+            _ => {}
+        }
+    }
+
+    fn process(&mut self, msg: Message) -> Result<bool, DecodeError> {
+        let updated = match self {
+            MachineWrapper::Initted(decoder) => decoder.update(msg)?,
+            MachineWrapper::Started(decoder) => {
+                if !decoder.accepts(&msg) {
+                    log(&format!(
+                        "[!] Dropped {}, it does not fit the metadata",
+                        msg.label()
+                    ));
+                    return Ok(false);
+                }
+                if let Message::Piece { index, data, .. } = &msg {
+                    decoder.note_chunk(*index, data.len());
+                }
+                let updated = decoder.update(msg.clone())?;
+                if updated {
+                    decoder.recover(&msg);
+                }
+                updated
+            }
+            MachineWrapper::Finished(_) => false,
+        };
+        if updated {
+            self.try_evolve()
+        };
+        Ok(updated)
+    }
+}
+
+/// Frames are tracked per session, so a stale screen or a second sender in view
+/// never mixes its pieces into the transfer being received.
 #[wasm_bindgen]
 pub struct Decoder {
     scanner: Quirc,
-    decoder: MachineWrapper,
+    sessions: IndexMap<Option<u32>, MachineWrapper>,
+    started_at: HashMap<Option<u32>, f64>,
+    calibration: Calibration,
+    trusted_keys: Vec<VerifyingKey>,
+    max_size: usize,
+    /// Sessions given up on and why, their frames are ignored from then on.
+    refused: IndexMap<Option<u32>, DecodeError>,
 }
 
 #[wasm_bindgen]
@@ -229,124 +781,262 @@ impl Decoder {
     pub fn new() -> Self {
         Decoder {
             scanner: Quirc::default(),
-            decoder: MachineWrapper::Initted(Machine::default()),
+            sessions: IndexMap::new(),
+            started_at: HashMap::new(),
+            calibration: Calibration::default(),
+            trusted_keys: Vec::new(),
+            max_size: DEFAULT_MAX_SIZE,
+            refused: IndexMap::new(),
         }
     }
 
+    /// Refuses files larger than `bytes`, before and after decompressing.
+    pub fn set_max_size(&mut self, bytes: usize) {
+        self.max_size = bytes;
+    }
+
+    /// Adds a sender public key, in hex as printed by `qrtransfer keygen`.
+    pub fn trust_key(&mut self, key: &str) -> Result<(), String> {
+        self.trusted_keys.push(sign::parse_key(key)?);
+        Ok(())
+    }
+
+    fn active(&self) -> Option<(&Option<u32>, &MachineWrapper)> {
+        self.sessions
+            .iter()
+            .rev()
+            .max_by_key(|(_, machine)| machine.rank())
+    }
+
     pub fn get_progress(&self) -> String {
-        match &self.decoder {
-            MachineWrapper::Initted(_) => "No METADATA yet.".to_string(),
-            MachineWrapper::Finished(_) => "Finished.".to_string(),
-            MachineWrapper::Started(machine) => {
-                let mut expecting = machine.expecting().into_iter().collect::<Vec<&String>>();
-                expecting.sort_by(|x, y| {
-                    if x == &"METADATA" {
-                        Ordering::Less
-                    } else if y == &"METADATA" {
-                        Ordering::Greater
-                    } else {
-                        x.parse::<usize>()
-                            .unwrap()
-                            .cmp(&y.parse::<usize>().unwrap())
-                    }
-                });
-                format!(
-                    "{}/{}, expecting: {}.",
-                    machine.state.metadata.length + 1 - expecting.len(),
-                    machine.state.metadata.length + 1,
-                    expecting
-                        .into_iter()
-                        .map(|s| s.to_string())
-                        .collect::<Vec<String>>()
-                        .join(", ")
-                )
-            }
-        }
+        self.progress().to_string()
+    }
+
+    /// `progress()` as a plain JS object.
+    #[wasm_bindgen(js_name = progress)]
+    pub fn progress_js(&self) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.progress())?)
+    }
+
+    /// The largest QR version whose test codes all came through, for the
+    /// user to type into the sender.
+    pub fn calibrated_version(&self) -> Option<usize> {
+        self.calibration.best()
+    }
+
+    /// Sessions given up on, see `progress`.
+    pub fn refused_sessions(&self) -> usize {
+        self.refused.len()
     }
 
     pub fn is_finished(&self) -> bool {
-        matches!(&self.decoder, MachineWrapper::Finished(_))
+        self.sessions
+            .values()
+            .any(|machine| matches!(machine, MachineWrapper::Finished(_)))
     }
 
-    fn try_evolve(&mut self) {
-        match &mut self.decoder {
-            MachineWrapper::Initted(decoder) => {
-                if decoder.state.metadata.is_some() {
-                    self.decoder = MachineWrapper::Started(take(decoder).into());
-                    self.try_evolve(); // may evolve agian
-                }
+    pub fn process_chunk(&mut self, chunk: String) -> Result<bool, DecodeError> {
+        self.process_bytes(chunk.as_bytes())
+    }
+
+    /// Processes a QR code payload, binary or text.
+    pub fn process_bytes(&mut self, chunk: &[u8]) -> Result<bool, DecodeError> {
+        let frame = Frame::decode(chunk).map_err(DecodeError::Malformed)?;
+        self.process_frame(frame)
+    }
+
+    pub fn scan(&mut self, width: u32, height: u32, data: Vec<u8>) -> Result<usize, DecodeError> {
+        let img: RgbaImage = ImageBuffer::from_raw(width, height, data).ok_or(
+            DecodeError::Malformed(format!("Image data does not fit {}x{}", width, height)),
+        )?;
+        let img_gray = DynamicImage::ImageRgba8(img).into_luma8();
+        Ok(self.scan_gray(&img_gray)?.len())
+    }
+
+    pub fn get_finished(self) -> Result<Finished, DecodeError> {
+        let progress = self.get_progress();
+        self.sessions
+            .into_values()
+            .find_map(|machine| match machine {
+                MachineWrapper::Finished(machine) => Some(machine.state),
+                _ => None,
+            })
+            .map(|finished| Finished {
+                trusted_keys: self.trusted_keys,
+                max_size: self.max_size,
+                ..finished
+            })
+            .ok_or(DecodeError::Incomplete(progress))
+    }
+}
+
+impl Decoder {
+    pub fn progress(&self) -> Progress {
+        let refused = self
+            .refused
+            .iter()
+            .map(|(session, e)| format!("{}: {}", session_name(*session), e))
+            .collect();
+        let Some((session, machine)) = self.active() else {
+            if !self.calibration.is_empty() {
+                return Progress {
+                    calibration: self.calibration.levels(),
+                    refused,
+                    ..Progress::new(State::Calibrating)
+                };
             }
-            MachineWrapper::Started(decoder) => {
-                if decoder.check_finished() {
-                    self.decoder = MachineWrapper::Finished(take(decoder).into());
-                }
+            return Progress {
+                refused,
+                ..MachineWrapper::default().progress()
+            };
+        };
+
+        let mut progress = Progress {
+            session: *session,
+            calibration: self.calibration.levels(),
+            refused,
+            ..machine.progress()
+        };
+        if let Some(&started_at) = self.started_at.get(session) {
+            progress.elapsed_ms = now() - started_at;
+            let remaining = progress.total.map(|total| total - progress.received);
+            if let Some(remaining) = remaining.filter(|_| progress.received > 0) {
+                progress.eta_ms =
+                    Some(progress.elapsed_ms / progress.received as f64 * remaining as f64);
             }
-            _ => {}
         }
+        for (_, other) in self.sessions.iter().filter(|(other, _)| *other != session) {
+            progress.ignored_frames += other.received();
+            progress.ignored_sessions += 1;
+        }
+        progress
     }
 
-    pub fn process_chunk(&mut self, chunk: String) -> bool {
-        let msg = match Message::from_str(&chunk) {
-            Ok(msg) => msg,
-            Err(e) => {
-                log(&format!("Failed to parse message: {}", e));
-                return false;
+    /// Refuses a transfer announcing more than the receiver accepts, before
+    /// anything is set aside for its pieces. The data sent is never larger
+    /// than the file, compression is skipped when it does not help, and every
+    /// piece but the last carries at least `MIN_CHUNK_SIZE` bytes, which
+    /// bounds the piece count too.
+    fn check_size(&self, metadata: &Metadata) -> Result<(), DecodeError> {
+        let size = metadata.size.unwrap_or(0);
+        let sent = metadata.size.unwrap_or(self.max_size).min(self.max_size) + crypto::OVERHEAD;
+        if size > self.max_size || metadata.length > sent.div_ceil(MIN_CHUNK_SIZE) {
+            return Err(DecodeError::TooLarge {
+                limit: self.max_size,
+            });
+        }
+        Ok(())
+    }
+
+    fn process_frame(&mut self, frame: Frame) -> Result<bool, DecodeError> {
+        if let Message::Calibration {
+            version,
+            index,
+            total,
+            ..
+        } = frame.message
+        {
+            return Ok(self.calibration.add(version, index, total));
+        }
+
+        if self.refused.contains_key(&frame.session) {
+            return Ok(false);
+        }
+        if let Message::Metadata(metadata) = &frame.message {
+            if let Err(e) = self.check_size(metadata) {
+                self.refuse(frame.session, e);
+                return Ok(false);
             }
-        };
+        }
 
-        let updated = match &mut self.decoder {
-            MachineWrapper::Initted(decoder) => {
-                let updated = decoder.update(msg.clone());
-                if updated {
-                    if let Message::Metadata(ref metadata) = msg {
-                        decoder.state.metadata = Some(metadata.clone());
-                    }
-                }
-                updated
+        let waiting = self
+            .sessions
+            .get(&frame.session)
+            .is_none_or(|machine| matches!(machine, MachineWrapper::Initted(_)));
+        let early: usize = self.sessions.values().map(MachineWrapper::early).sum();
+        if waiting && early >= MAX_EARLY_FRAMES && !matches!(frame.message, Message::Metadata(_)) {
+            return Ok(false);
+        }
+        if !self.sessions.contains_key(&frame.session) && self.sessions.len() >= MAX_SESSIONS {
+            self.evict();
+        }
+
+        self.started_at.entry(frame.session).or_insert_with(now);
+        let machine = self.sessions.entry(frame.session).or_insert_with(|| {
+            if let Some(session) = frame.session {
+                log(&format!("[*] New session: {:08X}", session));
             }
-            MachineWrapper::Started(decoder) => decoder.update(msg.clone()),
-            MachineWrapper::Finished(_) => false,
-        };
-        if updated {
-            self.try_evolve()
-        };
-        updated
+            MachineWrapper::default()
+        });
+        match machine.process(frame.message) {
+            Ok(updated) => Ok(updated),
+            Err(e) => {
+                self.refuse(frame.session, e);
+                Ok(false)
+            }
+        }
     }
 
-    pub fn scan(&mut self, width: u32, height: u32, data: Vec<u8>) -> usize {
-        let img: RgbaImage = ImageBuffer::from_raw(width, height, data).unwrap();
-        let img_gray = DynamicImage::ImageRgba8(img).into_luma8();
+    /// Gives up on one session, a sender announcing too large a file or two
+    /// different ones, and keeps receiving the others.
+    fn refuse(&mut self, session: Option<u32>, e: DecodeError) {
+        log(&format!("[!] Refused {}: {}", session_name(session), e));
+        self.sessions.shift_remove(&session);
+        self.started_at.remove(&session);
+        if !self.refused.contains_key(&session) && self.refused.len() >= MAX_SESSIONS {
+            self.refused.shift_remove_index(0);
+        }
+        self.refused.insert(session, e);
+    }
+
+    /// Makes room for a new session, dropping the oldest of those least along.
+    fn evict(&mut self) {
+        let oldest = self
+            .sessions
+            .values()
+            .enumerate()
+            .min_by_key(|(_, machine)| machine.rank().0)
+            .map(|(i, _)| i);
+        if let Some((session, _)) = oldest.and_then(|i| self.sessions.shift_remove_index(i)) {
+            log(&format!(
+                "[!] Dropped {}, too many senders",
+                session_name(session)
+            ));
+            self.started_at.remove(&session);
+        }
+    }
+
+    /// Scans a grayscale image, returning the messages that were new. Codes
+    /// that are no frames at all are skipped, any QR code may be in view.
+    pub fn scan_gray(&mut self, img_gray: &GrayImage) -> Result<Vec<Message>, DecodeError> {
         let codes: Vec<_> = self
             .scanner
             .identify(
                 img_gray.width() as usize,
                 img_gray.height() as usize,
-                &img_gray,
+                img_gray,
             )
             .flatten()
             .collect();
 
-        let mut counter = 0;
+        let mut new = Vec::new();
         for code in codes {
-            {
-                if let Ok(decoded) = code.decode() {
-                    if let Ok(msg) = String::from_utf8(decoded.payload) {
-                        if self.process_chunk(msg) {
-                            counter += 1;
-                        }
+            let Ok(decoded) = code.decode() else {
+                continue;
+            };
+            match Frame::decode(&decoded.payload) {
+                Ok(frame) => {
+                    let message = frame.message.clone();
+                    match self.process_frame(frame) {
+                        Ok(true) => new.push(message),
+                        Ok(false) => {}
+                        Err(e) => log(&e.to_string()),
                     }
                 }
+                Err(e) => log(&format!("Failed to parse message: {}", e)),
             }
         }
-        counter
-    }
-
-    pub fn get_finished(self) -> Finished {
-        if let MachineWrapper::Finished(machine) = self.decoder {
-            machine.state
-        } else {
-            panic!("Should be finished by now.")
-        }
+        Ok(new)
     }
 }
 
@@ -354,18 +1044,22 @@ impl Decoder {
 fn test_decoder() {
     let mut decoder = Decoder::new();
 
-    decoder.process_chunk(
-        "METADATA:dGVzdF9xcnRyYW5zZmVyLnR4dA==,2,bf0c337e1d303f70a099465a726ef627ef91c4db"
-            .to_string(),
-    );
+    decoder
+        .process_chunk(
+            "METADATA:dGVzdF9xcnRyYW5zZmVyLnR4dA==,2,bf0c337e1d303f70a099465a726ef627ef91c4db"
+                .to_string(),
+        )
+        .unwrap();
     println!("{}", decoder.get_progress());
-    decoder.process_chunk("1:G7YA4MVyW6oXCn6KbhrMx0C9wiM8U0+WhRrPCKomVFU2OVunN7y5HhGHtMnB5hPiEp8t9bCBGnjYey3YRlLaTxOWCBIsfQ5bSXyDSXg2x69btma2UFu4x4svyoIGUQyUNPFGXw==".to_string());
+    decoder.process_chunk("1:G7YA4MVyW6oXCn6KbhrMx0C9wiM8U0+WhRrPCKomVFU2OVunN7y5HhGHtMnB5hPiEp8t9bCBGnjYey3YRlLaTxOWCBIsfQ5bSXyDSXg2x69btma2UFu4x4svyoIGUQyUNPFGXw==".to_string()).unwrap();
     println!("{}", decoder.get_progress());
-    decoder.process_chunk("2:3fsUxrFm4KoZKOUb".to_string());
+    decoder
+        .process_chunk("2:3fsUxrFm4KoZKOUb".to_string())
+        .unwrap();
     println!("{}", decoder.get_progress());
 
-    let res = decoder.get_finished();
-    let decoded_data = BASE64_STANDARD.decode(res.to_base64()).unwrap();
+    let res = decoder.get_finished().unwrap();
+    let decoded_data = BASE64_STANDARD.decode(res.to_base64().unwrap()).unwrap();
     let decoded_data = String::from_utf8(decoded_data).unwrap();
     println!("{}", decoded_data);
     assert_eq!(decoded_data, "Transfer your file from an air gapped computer to iOS/iPhone/iPad using only qrcode, no wifi/usb/bluetooth needed. This is a proof-of-concept project, implemented in Rust WebAssembly.");
@@ -374,15 +1068,391 @@ fn test_decoder() {
 #[test]
 fn test_when_metadata_came_at_last() {
     let mut decoder = Decoder::new();
-    decoder.process_chunk("1:G7YA4MVyW6oXCn6KbhrMx0C9wiM8U0+WhRrPCKomVFU2OVunN7y5HhGHtMnB5hPiEp8t9bCBGnjYey3YRlLaTxOWCBIsfQ5bSXyDSXg2x69btma2UFu4x4svyoIGUQyUNPFGXw==".to_string());
-    decoder.process_chunk("2:3fsUxrFm4KoZKOUb".to_string());
+    decoder.process_chunk("1:G7YA4MVyW6oXCn6KbhrMx0C9wiM8U0+WhRrPCKomVFU2OVunN7y5HhGHtMnB5hPiEp8t9bCBGnjYey3YRlLaTxOWCBIsfQ5bSXyDSXg2x69btma2UFu4x4svyoIGUQyUNPFGXw==".to_string()).unwrap();
+    decoder
+        .process_chunk("2:3fsUxrFm4KoZKOUb".to_string())
+        .unwrap();
+    decoder
+        .process_chunk(
+            "METADATA:dGVzdF9xcnRyYW5zZmVyLnR4dA==,2,bf0c337e1d303f70a099465a726ef627ef91c4db"
+                .to_string(),
+        )
+        .unwrap();
+
+    let res = decoder.get_finished().unwrap();
+    let decoded_data = BASE64_STANDARD.decode(res.to_base64().unwrap()).unwrap();
+    let decoded_data = String::from_utf8(decoded_data).unwrap();
+    assert_eq!(decoded_data, "Transfer your file from an air gapped computer to iOS/iPhone/iPad using only qrcode, no wifi/usb/bluetooth needed. This is a proof-of-concept project, implemented in Rust WebAssembly.");
+}
+
+#[test]
+fn test_foreign_session_ignored() {
+    let mut decoder = Decoder::new();
+
+    decoder
+        .process_chunk("QRT:2:00000001:P:1,AAAA".to_string())
+        .unwrap();
     decoder.process_chunk(
-        "METADATA:dGVzdF9xcnRyYW5zZmVyLnR4dA==,2,bf0c337e1d303f70a099465a726ef627ef91c4db"
+        "QRT:2:BF0C337E:M:dGVzdF9xcnRyYW5zZmVyLnR4dA==,2,bf0c337e1d303f70a099465a726ef627ef91c4db"
             .to_string(),
+    ).unwrap();
+    decoder.process_chunk("QRT:2:BF0C337E:P:1,G7YA4MVyW6oXCn6KbhrMx0C9wiM8U0+WhRrPCKomVFU2OVunN7y5HhGHtMnB5hPiEp8t9bCBGnjYey3YRlLaTxOWCBIsfQ5bSXyDSXg2x69btma2UFu4x4svyoIGUQyUNPFGXw==".to_string()).unwrap();
+    decoder
+        .process_chunk("QRT:2:00000001:P:2,AAAA".to_string())
+        .unwrap();
+    assert_eq!(
+        decoder.get_progress(),
+        "2/3, expecting: 2. Ignored 2 frames from 1 other session(s)."
     );
+    let progress = decoder.progress();
+    assert_eq!(progress.state, State::Receiving);
+    assert_eq!((progress.received, progress.total), (2, Some(3)));
+    assert_eq!(progress.missing, vec![2]);
+    assert_eq!(progress.session, Some(0xBF0C337E));
+    assert_eq!(progress.file_name.as_deref(), Some("test_qrtransfer.txt"));
+    assert!(progress.bytes > 0 && progress.eta_ms.is_some());
+    assert_eq!((progress.ignored_frames, progress.ignored_sessions), (2, 1));
+    decoder
+        .process_chunk("QRT:2:BF0C337E:P:2,3fsUxrFm4KoZKOUb".to_string())
+        .unwrap();
 
-    let res = decoder.get_finished();
-    let decoded_data = BASE64_STANDARD.decode(res.to_base64()).unwrap();
+    let res = decoder.get_finished().unwrap();
+    let decoded_data = BASE64_STANDARD.decode(res.to_base64().unwrap()).unwrap();
     let decoded_data = String::from_utf8(decoded_data).unwrap();
     assert_eq!(decoded_data, "Transfer your file from an air gapped computer to iOS/iPhone/iPad using only qrcode, no wifi/usb/bluetooth needed. This is a proof-of-concept project, implemented in Rust WebAssembly.");
 }
+
+#[test]
+fn test_decode_errors() {
+    let piece_1 = "1:G7YA4MVyW6oXCn6KbhrMx0C9wiM8U0+WhRrPCKomVFU2OVunN7y5HhGHtMnB5hPiEp8t9bCBGnjYey3YRlLaTxOWCBIsfQ5bSXyDSXg2x69btma2UFu4x4svyoIGUQyUNPFGXw==";
+    let piece_2 = "2:3fsUxrFm4KoZKOUb";
+
+    assert!(matches!(
+        Decoder::new().get_finished(),
+        Err(DecodeError::Incomplete(_))
+    ));
+    assert!(matches!(
+        Decoder::new().process_chunk("not a frame".to_string()),
+        Err(DecodeError::Malformed(_))
+    ));
+
+    // a session announcing two files is given up on
+    let mut decoder = Decoder::new();
+    decoder
+        .process_chunk("METADATA:dGVzdF9xcnRyYW5zZmVyLnR4dA==,2,0000".to_string())
+        .unwrap();
+    assert_eq!(
+        decoder.process_chunk("METADATA:b3RoZXIudHh0,2,0000".to_string()),
+        Ok(false)
+    );
+    assert!(decoder.progress().refused[0].starts_with("frames without a session: Conflicting"));
+    assert_eq!(decoder.process_chunk(piece_1.to_string()), Ok(false));
+
+    let mut decoder = Decoder::new();
+    decoder
+        .process_chunk("METADATA:dGVzdF9xcnRyYW5zZmVyLnR4dA==,2,0000".to_string())
+        .unwrap();
+    decoder.process_chunk(piece_1.to_string()).unwrap();
+    decoder.process_chunk(piece_2.to_string()).unwrap();
+    let finished = decoder.get_finished().unwrap();
+    assert_eq!(finished.get_name().unwrap(), "test_qrtransfer.txt");
+    assert!(matches!(
+        finished.to_bytes(),
+        Err(DecodeError::HashMismatch { .. })
+    ));
+
+    let mut decoder = Decoder::new();
+    decoder
+        .process_chunk("METADATA:bm90 YmFzZTY0,2,0000".to_string())
+        .unwrap();
+    decoder.process_chunk(piece_1.to_string()).unwrap();
+    decoder.process_chunk(piece_2.to_string()).unwrap();
+    assert!(matches!(
+        decoder.get_finished().unwrap().get_name(),
+        Err(DecodeError::BadBase64(_))
+    ));
+}
+
+#[test]
+fn test_corrupted_piece_replaced() {
+    let data = b"Transfer your file".to_vec();
+    let metadata = Metadata {
+        hash_algorithm: crate::utils::HashAlgorithm::Sha256,
+        ..Metadata::new(
+            BASE64_STANDARD.encode("test.txt"),
+            2,
+            hash(&data).to_string(),
+        )
+    };
+    let Message::Piece { checksum, .. } = Message::piece(1, data[..9].to_vec()) else {
+        unreachable!()
+    };
+    let misread = Message::Piece {
+        index: 1,
+        data: b"Transfor ".to_vec(),
+        checksum,
+    };
+
+    let mut decoder = Decoder::new();
+    for msg in [
+        Message::Metadata(metadata),
+        misread,
+        Message::piece(2, data[9..].to_vec()),
+    ] {
+        decoder.process_frame(Frame::new(1, msg)).unwrap();
+    }
+    assert_eq!(
+        decoder.get_progress(),
+        "2/3, expecting: 1. Discarded 1 corrupted piece(s)."
+    );
+
+    assert!(decoder
+        .process_frame(Frame::new(1, Message::piece(1, data[..9].to_vec())))
+        .unwrap());
+    assert_eq!(decoder.get_finished().unwrap().data, data);
+}
+
+#[test]
+fn test_conflicting_pieces() {
+    let data = b"Transfer your file".to_vec();
+    let receive = |pieces: &[(usize, &[u8])]| {
+        let metadata = Metadata {
+            hash_algorithm: crate::utils::HashAlgorithm::Sha256,
+            ..Metadata::new(BASE64_STANDARD.encode("test.txt"), 2, hash(&data))
+        };
+        let mut decoder = Decoder::new();
+        decoder
+            .process_frame(Frame::new(1, Message::Metadata(metadata)))
+            .unwrap();
+        for &(index, piece) in pieces {
+            decoder
+                .process_frame(Frame::new(1, Message::piece(index, piece.to_vec())))
+                .unwrap();
+        }
+        decoder.get_finished().unwrap()
+    };
+
+    // a second sender's piece 1 is not mixed in, the hash tells them apart
+    let finished = receive(&[(1, b"Transfer "), (1, b"Another  "), (2, b"your file")]);
+    assert_eq!(finished.data, data);
+    assert_eq!(finished.check_integrity(), Ok(()));
+
+    let finished = receive(&[(1, b"Another  "), (1, b"Other    "), (2, b"your file")]);
+    assert_eq!(
+        finished.check_integrity(),
+        Err(DecodeError::AmbiguousPieces(vec![1]))
+    );
+}
+
+#[test]
+fn test_size_limits() {
+    use crate::compress::Codec;
+
+    let zeros = vec![0; 100_000];
+    let compressed = Codec::default().compress(&zeros);
+    let metadata = Metadata {
+        hash_algorithm: crate::utils::HashAlgorithm::Sha256,
+        size: Some(zeros.len()),
+        ..Metadata::new(BASE64_STANDARD.encode("zeros"), 1, hash(&compressed))
+    };
+
+    // refused, while another sender in view goes on
+    let mut decoder = Decoder::new();
+    decoder.set_max_size(10_000);
+    decoder
+        .process_frame(Frame::new(2, Message::piece(1, b"small".to_vec())))
+        .unwrap();
+    assert_eq!(
+        decoder.process_frame(Frame::new(1, Message::Metadata(metadata.clone()))),
+        Ok(false)
+    );
+    assert_eq!(
+        decoder.progress().refused,
+        vec!["session 00000001: The file is larger than the 10000 bytes allowed"]
+    );
+    assert_eq!(decoder.progress().session, Some(2));
+    assert!(decoder
+        .process_frame(Frame::new(2, Message::piece(2, b"small".to_vec())))
+        .unwrap());
+
+    // a few bytes can't come in a million pieces
+    for (size, length) in [
+        (None, usize::MAX),
+        (Some(1_000), 1_000_000),
+        (None, 100_000_000),
+    ] {
+        let many = Metadata {
+            length,
+            size,
+            ..metadata.clone()
+        };
+        let mut decoder = Decoder::new();
+        decoder
+            .process_frame(Frame::new(1, Message::Metadata(many)))
+            .unwrap();
+        assert_eq!(decoder.progress().refused.len(), 1);
+    }
+
+    // frames before the metadata are held up to a limit, later ones beyond
+    // the announced count are dropped
+    let mut decoder = Decoder::new();
+    for index in 1..=MAX_EARLY_FRAMES + 10 {
+        decoder
+            .process_frame(Frame::new(2, Message::piece(index, vec![0; 16])))
+            .unwrap();
+    }
+    assert_eq!(decoder.progress().received, MAX_EARLY_FRAMES);
+    let announced = Metadata {
+        length: 4200,
+        size: Some(4200 * 16),
+        ..metadata.clone()
+    };
+    decoder
+        .process_frame(Frame::new(2, Message::Metadata(announced)))
+        .unwrap();
+    assert_eq!(decoder.progress().received, MAX_EARLY_FRAMES + 1);
+    assert!(!decoder
+        .process_frame(Frame::new(2, Message::piece(5000, vec![0; 16])))
+        .unwrap());
+    assert!(decoder
+        .process_frame(Frame::new(2, Message::piece(4200, vec![0; 16])))
+        .unwrap());
+
+    // a screen making up a new session for every frame is held to the same
+    // limits, and the session already started survives it
+    let mut decoder = Decoder::new();
+    decoder
+        .process_frame(Frame::new(1, Message::Metadata(metadata.clone())))
+        .unwrap();
+    for session in 2..20_000 {
+        for index in 1..=2 {
+            decoder
+                .process_frame(Frame::new(session, Message::piece(index, vec![0; 16])))
+                .unwrap();
+        }
+    }
+    assert_eq!(decoder.sessions.len(), MAX_SESSIONS);
+    assert!(decoder.started_at.len() <= MAX_SESSIONS);
+    assert!(decoder.sessions.contains_key(&Some(1)));
+    let early: usize = decoder.sessions.values().map(MachineWrapper::early).sum();
+    assert!(early <= MAX_EARLY_FRAMES);
+    for session in 20_000..20_100 {
+        decoder
+            .process_frame(Frame::new(
+                session,
+                Message::Metadata(Metadata {
+                    size: Some(1 << 30),
+                    ..metadata.clone()
+                }),
+            ))
+            .unwrap();
+    }
+    assert_eq!(decoder.refused_sessions(), MAX_SESSIONS);
+
+    // a sender understating the size gets no more than it announced
+    let understated = Metadata {
+        size: Some(1_000),
+        ..metadata
+    };
+    let mut decoder = Decoder::new();
+    for msg in [
+        Message::Metadata(understated),
+        Message::piece(1, compressed),
+    ] {
+        decoder.process_frame(Frame::new(1, msg)).unwrap();
+    }
+    assert_eq!(
+        decoder.get_finished().unwrap().to_bytes(),
+        Err(DecodeError::TooLarge { limit: 1_000 })
+    );
+}
+
+#[test]
+fn test_forged_symbols() {
+    let data = b"Transfer your file".to_vec();
+    let metadata = Metadata {
+        hash_algorithm: crate::utils::HashAlgorithm::Sha256,
+        ..Metadata::new(BASE64_STANDARD.encode("test.txt"), 2, hash(&data))
+    };
+    let symbol = |degree, size, data: &[u8]| Message::Symbol {
+        seed: 1,
+        degree,
+        size,
+        data: data.to_vec(),
+    };
+
+    let mut decoder = Decoder::new();
+    // held until the metadata comes, then dropped
+    decoder
+        .process_frame(Frame::new(1, symbol(1, 18, b"")))
+        .unwrap();
+    decoder
+        .process_frame(Frame::new(1, Message::Metadata(metadata)))
+        .unwrap();
+    for forged in [
+        symbol(1, 18, b""),
+        symbol(1, usize::MAX, b"Transfer "),
+        symbol(1, 1 << 40, b"T"),
+        symbol(0, 18, b"Transfer "),
+        symbol(3, 18, b"Transfer "),
+    ] {
+        assert_eq!(decoder.process_frame(Frame::new(1, forged)), Ok(false));
+    }
+    assert!(decoder
+        .process_frame(Frame::new(1, Message::piece(1, data[..9].to_vec())))
+        .unwrap());
+    // pieces are 9 bytes, so a symbol of 6 can't be right
+    assert_eq!(
+        decoder.process_frame(Frame::new(1, symbol(1, 18, b"Transf"))),
+        Ok(false)
+    );
+    decoder
+        .process_frame(Frame::new(1, Message::piece(2, data[9..].to_vec())))
+        .unwrap();
+    assert_eq!(decoder.get_finished().unwrap().data, data);
+}
+
+#[test]
+fn test_forged_parity() {
+    let data = b"Transfer your file".to_vec();
+    let metadata = Metadata {
+        hash_algorithm: crate::utils::HashAlgorithm::Sha256,
+        ..Metadata::new(BASE64_STANDARD.encode("test.txt"), 2, hash(&data))
+    };
+    let blocks = data.chunks(9).collect::<Vec<&[u8]>>();
+    let shard = parity::encode(&blocks, 9, 50).remove(0);
+    let parity = |groups, group, total, size, data: &[u8]| Message::Parity {
+        size,
+        groups,
+        group,
+        total,
+        index: 0,
+        data: data.to_vec(),
+    };
+
+    let mut decoder = Decoder::new();
+    decoder
+        .process_frame(Frame::new(1, parity(0, 0, 1, 18, &shard.data)))
+        .unwrap();
+    decoder
+        .process_frame(Frame::new(1, Message::Metadata(metadata)))
+        .unwrap();
+    for forged in [
+        parity(0, 0, 1, 18, &shard.data),
+        parity(1, 1, 1, 18, &shard.data),
+        parity(2, 0, 1, 18, &shard.data),
+        parity(1, 0, usize::MAX, 18, &shard.data),
+        parity(1, 0, 1 << 40, 18, &shard.data),
+        parity(1, 0, 1, usize::MAX, &shard.data),
+        parity(1, 0, 1, 18, b""),
+    ] {
+        assert_eq!(decoder.process_frame(Frame::new(1, forged)), Ok(false));
+    }
+    decoder
+        .process_frame(Frame::new(1, Message::piece(1, data[..9].to_vec())))
+        .unwrap();
+    decoder
+        .process_frame(Frame::new(1, parity(1, 0, shard.total, 18, &shard.data)))
+        .unwrap();
+    assert_eq!(decoder.get_finished().unwrap().data, data);
+}
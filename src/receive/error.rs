@@ -0,0 +1,87 @@
+use crate::protocol::Metadata;
+use std::fmt;
+use wasm_bindgen::JsValue;
+
+/// Why a transfer could not be received. Surfaces in JS as an `Error` with the
+/// message below.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The reassembled data does not match the hash in the metadata.
+    HashMismatch {
+        expected: String,
+        actual: String,
+    },
+    /// The compressed data ended before the end of the stream.
+    Truncated,
+    Decompression(String),
+    /// The file name in the metadata is not valid base64.
+    BadBase64(String),
+    /// A session announced two different files.
+    ConflictingMetadata {
+        expected: Box<Metadata>,
+        received: Box<Metadata>,
+    },
+    /// These pieces came in different versions and no choice among them
+    /// matches the hash.
+    AmbiguousPieces(Vec<usize>),
+    /// The transfer is larger than the receiver accepts, see
+    /// `Decoder::set_max_size`.
+    TooLarge {
+        limit: usize,
+    },
+    /// A payload that is not a frame of any known format.
+    Malformed(String),
+    /// The transfer was asked for before all of its pieces arrived.
+    Incomplete(String),
+    /// The file list of a multi-file transfer can't be read.
+    Manifest(String),
+    /// The transfer is encrypted and no passphrase was given.
+    PassphraseRequired,
+    /// Wrong passphrase, or the encrypted data was altered.
+    Decryption,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::HashMismatch { expected, actual } => {
+                write!(f, "Hash mismatch, expected {}, got {}", expected, actual)
+            }
+            DecodeError::Truncated => write!(f, "The received data is truncated"),
+            DecodeError::Decompression(e) => write!(f, "Failed decompressing: {}", e),
+            DecodeError::BadBase64(e) => write!(f, "Failed to decode the file name: {}", e),
+            DecodeError::ConflictingMetadata { expected, received } => write!(
+                f,
+                "Conflicting metadata in one session, expected '{}', got '{}'",
+                expected, received
+            ),
+            DecodeError::AmbiguousPieces(indices) => write!(
+                f,
+                "Conflicting copies of pieces {} were received, no combination matches the hash",
+                indices
+                    .iter()
+                    .map(|index| index.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            DecodeError::TooLarge { limit } => {
+                write!(f, "The file is larger than the {} bytes allowed", limit)
+            }
+            DecodeError::Malformed(e) => write!(f, "Failed to parse message: {}", e),
+            DecodeError::Incomplete(progress) => write!(f, "Not finished yet. {}", progress),
+            DecodeError::Manifest(e) => write!(f, "Failed to read the file list: {}", e),
+            DecodeError::PassphraseRequired => {
+                write!(f, "The file is encrypted, enter the passphrase")
+            }
+            DecodeError::Decryption => write!(f, "Wrong passphrase, or the data was altered"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<DecodeError> for JsValue {
+    fn from(e: DecodeError) -> JsValue {
+        js_sys::Error::new(&e.to_string()).into()
+    }
+}
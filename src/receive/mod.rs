@@ -1,6 +1,11 @@
 mod decoder;
+mod error;
+mod progress;
+pub mod video;
 
+use base64::{prelude::BASE64_STANDARD, Engine as _};
 use dioxus::prelude::*;
+use std::fmt;
 use std::sync::Arc;
 use std::sync::Mutex;
 use wasm_bindgen::closure::Closure;
@@ -15,7 +20,14 @@ use web_sys::{
 };
 
 pub use decoder::Decoder;
+use decoder::Finished;
+pub use error::DecodeError;
+pub use progress::{Progress, State};
 
+use crate::bundle;
+use crate::filename;
+use crate::sign::Verification;
+use crate::utils::log;
 use crate::CAMERA_FACING;
 
 fn beep(audio_context: &AudioContext, freq: f32, duration: f64, vol: f32) {
@@ -58,26 +70,132 @@ async fn beep_n(n: i32) {
     }
 }
 
-fn add_download(base64_data: &str, file_name: &str) {
+/// A download link, followed by who signed the transfer when given.
+fn add_download(
+    base64_data: &str,
+    file_name: &str,
+    mime_type: &str,
+    label: &str,
+    verification: Option<&Verification>,
+) -> web_sys::HtmlElement {
     let document = web_sys::window().unwrap().document().unwrap();
+    let div = document.create_element("div").unwrap();
     let a = document.create_element("a").unwrap();
-    let mime_type = mime_guess::from_path(file_name)
-        .first_or_octet_stream()
-        .to_string();
     a.set_attribute(
         "href",
         &format!("data:{};base64,{}", mime_type, base64_data),
     )
     .unwrap();
     a.set_attribute("download", file_name).unwrap();
-    a.set_inner_html("Download");
+    a.set_text_content(Some(label));
+    div.append_child(&a).unwrap();
+    if let Some(verification) = verification {
+        let color = match verification {
+            Verification::Trusted(_) => "var(--text-success)",
+            Verification::Invalid | Verification::Untrusted(_) => "var(--text-error)",
+            Verification::Unsigned => "var(--text-muted)",
+        };
+        let span = document.create_element("span").unwrap();
+        span.set_attribute("style", &format!("margin-left: 0.5em; color: {};", color))
+            .unwrap();
+        span.set_text_content(Some(&verification.to_string()));
+        div.append_child(&span).unwrap();
+    }
     document
         .get_element_by_id("receive")
         .unwrap()
-        .append_child(&a)
+        .append_child(&div)
         .unwrap();
-    let a: web_sys::HtmlElement = a.dyn_into().unwrap();
-    a.click();
+    a.dyn_into().unwrap()
+}
+
+/// Downloads the received file, or for several files an archive of them all,
+/// with a link to each file.
+fn add_downloads(finished: &Finished) -> Result<(), DecodeError> {
+    let verification = finished.verification();
+    if !finished.is_bundle() {
+        add_download(
+            &finished.to_base64()?,
+            &finished.get_safe_name()?,
+            &finished.mime_type(),
+            "Download",
+            Some(&verification),
+        )
+        .click();
+        return Ok(());
+    }
+    let files = finished.files()?;
+    let name = filename::sanitize(finished.get_name()?.trim_end_matches('/'));
+    add_download(
+        &BASE64_STANDARD.encode(bundle::tar(&files)),
+        &format!("{}.tar", name),
+        "application/x-tar",
+        &format!("Download all {} files", files.len()),
+        Some(&verification),
+    )
+    .click();
+    for (entry, data) in &files {
+        add_download(
+            &BASE64_STANDARD.encode(data),
+            &filename::sanitize(&entry.path),
+            mime_guess::from_path(&entry.path)
+                .first_or_octet_stream()
+                .essence_str(),
+            &entry.path,
+            None,
+        );
+    }
+    Ok(())
+}
+
+/// Asks for the passphrase until it opens the file or the user gives up.
+fn unlock(finished: &mut Finished) -> Result<(), DecodeError> {
+    if !finished.is_encrypted() {
+        return Ok(());
+    }
+    let window = web_sys::window().unwrap();
+    let mut message = format!("Passphrase for {}:", finished.get_safe_name()?);
+    loop {
+        let Some(passphrase) = window.prompt_with_message(&message).unwrap() else {
+            return Err(DecodeError::PassphraseRequired);
+        };
+        match finished.unlock(&passphrase) {
+            Err(DecodeError::Decryption) => message = "Wrong passphrase, try again:".to_string(),
+            result => return result,
+        }
+    }
+}
+
+/// Shows the error as text, it may quote a file name from the sender.
+fn show_error(element: &web_sys::Element, e: &impl fmt::Display) {
+    log(&format!("[*] {}", e));
+    let document = web_sys::window().unwrap().document().unwrap();
+    let span = document.create_element("span").unwrap();
+    span.set_attribute("style", "color: var(--text-error);")
+        .unwrap();
+    span.set_text_content(Some(&e.to_string()));
+    element.set_inner_html("");
+    element.append_child(&span).unwrap();
+}
+
+const TRUSTED_KEYS_ITEM: &str = "qrtransfer-trusted-keys";
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+/// Sender public keys to check signatures against, one per line, kept in the
+/// browser across visits.
+pub fn trusted_keys() -> String {
+    local_storage()
+        .and_then(|storage| storage.get_item(TRUSTED_KEYS_ITEM).ok()?)
+        .unwrap_or_default()
+}
+
+pub fn set_trusted_keys(keys: &str) {
+    if let Some(storage) = local_storage() {
+        let _ = storage.set_item(TRUSTED_KEYS_ITEM, keys);
+    }
 }
 
 pub async fn start_receiving() {
@@ -129,10 +247,18 @@ pub async fn start_receiving() {
         .dyn_into::<CanvasRenderingContext2d>()
         .unwrap();
 
-    let decoder = Arc::new(Mutex::new(Some(Decoder::new())));
+    let mut decoder = Decoder::new();
+    for key in trusted_keys().split_whitespace() {
+        if let Err(e) = decoder.trust_key(key) {
+            show_error(&cam_qr_result, &e);
+            return;
+        }
+    }
+    let decoder = Arc::new(Mutex::new(Some(decoder)));
 
     video.set_src_object(Some(&stream));
     let video_clone = video.clone();
+    let mut shown_refused = 0;
     let interval_closure = Closure::wrap(Box::new(move || {
         canvas.set_width(video_clone.video_width());
         canvas.set_height(video_clone.video_height());
@@ -149,6 +275,23 @@ pub async fn start_receiving() {
             canvas.height() as u32,
             my_image_data.data().to_vec(),
         );
+        let counter = match counter {
+            Ok(counter) => counter,
+            Err(e) => {
+                stop_receiving();
+                decoder.lock().unwrap().take();
+                show_error(&cam_qr_result, &e);
+                return;
+            }
+        };
+        // a sender refused in view says so, the others are still received
+        let refused = decoder.lock().unwrap().as_ref().unwrap().refused_sessions();
+        if counter == 0 && refused > shown_refused {
+            shown_refused = refused;
+            cam_qr_result.set_text_content(Some(
+                &decoder.lock().unwrap().as_mut().unwrap().get_progress(),
+            ));
+        }
         if counter > 0 {
             cam_qr_result.set_text_content(Some(
                 &decoder.lock().unwrap().as_mut().unwrap().get_progress(),
@@ -159,7 +302,13 @@ pub async fn start_receiving() {
             if decoder.lock().unwrap().as_mut().unwrap().is_finished() {
                 stop_receiving();
                 let finished = decoder.lock().unwrap().take().unwrap().get_finished();
-                add_download(&finished.to_base64(), &finished.get_name());
+                let download = finished.and_then(|mut finished| {
+                    unlock(&mut finished)?;
+                    add_downloads(&finished)
+                });
+                if let Err(e) = download {
+                    show_error(&cam_qr_result, &e);
+                }
             }
         }
     }) as Box<dyn FnMut()>);
@@ -0,0 +1,137 @@
+use crate::calibration::CalibrationLevel;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    /// Nothing scanned yet, or no metadata for the transfer yet.
+    Waiting,
+    /// Only calibration test codes were scanned.
+    Calibrating,
+    Receiving,
+    Finished,
+}
+
+/// Where the transfer being received stands. Counts include the metadata
+/// frame, so `received == total` once finished.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Progress {
+    pub state: State,
+    pub received: usize,
+    /// Known once the metadata arrived.
+    pub total: Option<usize>,
+    /// Indices of the pieces still missing, in order.
+    pub missing: Vec<usize>,
+    /// The transfer is signed and the signature frame did not come yet.
+    pub signature_pending: bool,
+    /// Piece data received so far.
+    pub bytes: usize,
+    pub session: Option<u32>,
+    pub file_name: Option<String>,
+    /// Since the first frame of this transfer.
+    pub elapsed_ms: f64,
+    /// At the rate frames came in so far.
+    pub eta_ms: Option<f64>,
+    /// Fountain symbols that could not be resolved yet.
+    pub pending_symbols: usize,
+    /// Pieces discarded for a bad checksum.
+    pub corrupted: usize,
+    /// Frames from other sessions in view.
+    pub ignored_frames: usize,
+    pub ignored_sessions: usize,
+    /// Sessions given up on, with the reason.
+    pub refused: Vec<String>,
+    pub calibration: Vec<CalibrationLevel>,
+}
+
+impl Progress {
+    pub fn new(state: State) -> Self {
+        Progress {
+            state,
+            received: 0,
+            total: None,
+            missing: Vec::new(),
+            signature_pending: false,
+            bytes: 0,
+            session: None,
+            file_name: None,
+            elapsed_ms: 0.0,
+            eta_ms: None,
+            pending_symbols: 0,
+            corrupted: 0,
+            ignored_frames: 0,
+            ignored_sessions: 0,
+            refused: Vec::new(),
+            calibration: Vec::new(),
+        }
+    }
+
+    /// The largest calibrated QR version, see `calibration`.
+    pub fn calibrated_version(&self) -> Option<usize> {
+        self.calibration
+            .iter()
+            .rev()
+            .find(|level| level.decoded >= level.total)
+            .map(|level| level.version)
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.state {
+            State::Waiting => write!(f, "No METADATA yet.")?,
+            State::Finished => write!(f, "Finished.")?,
+            State::Calibrating => {
+                let seen = self
+                    .calibration
+                    .iter()
+                    .map(|level| format!("v{} {}/{}", level.version, level.decoded, level.total))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                return match self.calibrated_version() {
+                    Some(version) => write!(
+                        f,
+                        "Calibration: enter {} on the sender. Decoded: {}.",
+                        version, seen
+                    ),
+                    None => write!(f, "Calibrating... Decoded: {}.", seen),
+                };
+            }
+            State::Receiving => {
+                let mut expecting = self
+                    .missing
+                    .iter()
+                    .map(|index| index.to_string())
+                    .collect::<Vec<String>>();
+                if self.signature_pending {
+                    expecting.insert(0, "SIGNATURE".to_string());
+                }
+                write!(
+                    f,
+                    "{}/{}, expecting: {}.",
+                    self.received,
+                    self.total.unwrap_or_default(),
+                    expecting.join(", ")
+                )?;
+                if self.pending_symbols > 0 {
+                    write!(f, " {} fountain symbols pending.", self.pending_symbols)?;
+                }
+            }
+        }
+        if self.corrupted > 0 {
+            write!(f, " Discarded {} corrupted piece(s).", self.corrupted)?;
+        }
+        if self.ignored_sessions > 0 {
+            write!(
+                f,
+                " Ignored {} frames from {} other session(s).",
+                self.ignored_frames, self.ignored_sessions
+            )?;
+        }
+        for refused in &self.refused {
+            write!(f, " Refused {}.", refused)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,386 @@
+//! Frames of a recorded video, for receiving from a phone video of the sender's
+//! screen. Only formats decodable in pure Rust are supported: Y4M, whose luma
+//! plane is all the scanner needs, and MJPEG, raw or inside an AVI file. Videos
+//! are read a frame at a time, never whole.
+
+use image::{GrayImage, ImageFormat};
+use std::io::{self, BufRead, Cursor, Read};
+
+const Y4M_MAGIC: &[u8] = b"YUV4MPEG2 ";
+const FRAME_MAGIC: &[u8] = b"FRAME";
+const JPEG_START: &[u8] = &[0xff, 0xd8, 0xff];
+const EOI: u8 = 0xd9;
+const SOS: u8 = 0xda;
+/// Longest Y4M header or frame line read.
+const MAX_LINE: u64 = 4096;
+/// Largest Y4M frame side accepted, far above any camera.
+const MAX_SIDE: u32 = 8192;
+
+pub type Frames<'a> = Box<dyn Iterator<Item = Result<GrayImage, String>> + 'a>;
+
+/// The grayscale frames of a Y4M or MJPEG video, decoded one at a time, or
+/// None if `reader` holds something else such as a photo. A JPEG is only
+/// taken for MJPEG when another one follows its end, a photo's EXIF
+/// thumbnail sits inside one of its segments.
+pub fn frames<'a>(mut reader: impl BufRead + 'a) -> Result<Option<Frames<'a>>, String> {
+    let mut head = Vec::new();
+    reader
+        .by_ref()
+        .take(12)
+        .read_to_end(&mut head)
+        .map_err(|e| e.to_string())?;
+    if head.starts_with(Y4M_MAGIC) {
+        return y4m_frames(Cursor::new(head).chain(reader)).map(Some);
+    }
+    if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"AVI ") {
+        return Ok(Some(mjpeg_frames(Cursor::new(head).chain(reader), None)));
+    }
+    if !head.starts_with(JPEG_START) {
+        return Ok(None);
+    }
+    let mut first = head;
+    let mut reader = Cursor::new(first.split_off(JPEG_START.len())).chain(reader);
+    if read_jpeg(&mut reader, &mut first).is_err() {
+        return Ok(None);
+    }
+    let mut next = Vec::new();
+    reader
+        .by_ref()
+        .take(JPEG_START.len() as u64)
+        .read_to_end(&mut next)
+        .map_err(|e| e.to_string())?;
+    if next != JPEG_START {
+        return Ok(None);
+    }
+    let reader = Cursor::new(next).chain(reader);
+    Ok(Some(mjpeg_frames(reader, Some(first))))
+}
+
+/// A line of at most `MAX_LINE` bytes, None at the end of the input.
+fn line(reader: &mut impl BufRead) -> Result<Option<String>, String> {
+    let mut line = Vec::new();
+    reader
+        .take(MAX_LINE)
+        .read_until(b'\n', &mut line)
+        .map_err(|e| e.to_string())?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err("Y4M header is truncated".to_string());
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|e| format!("Failed to parse Y4M header: {}", e))
+}
+
+fn y4m_frames<'a>(mut reader: impl BufRead + 'a) -> Result<Frames<'a>, String> {
+    let header = line(&mut reader)?.ok_or("Y4M header is truncated".to_string())?;
+    let (mut width, mut height, mut colorspace) = (0, 0, "420");
+    for param in header.split(' ').skip(1) {
+        let (tag, value) = param.split_at(1.min(param.len()));
+        let parse = |value: &str| {
+            value
+                .parse::<u32>()
+                .map_err(|e| format!("Failed to parse Y4M size: {}", e))
+        };
+        match tag {
+            "W" => width = parse(value)?,
+            "H" => height = parse(value)?,
+            "C" => colorspace = value,
+            _ => {}
+        }
+    }
+    if width == 0 || height == 0 {
+        return Err("Y4M header has no frame size".to_string());
+    }
+    if width > MAX_SIDE || height > MAX_SIDE {
+        return Err(format!(
+            "Y4M frames of {}x{} are too large, at most {}x{} are read",
+            width, height, MAX_SIDE, MAX_SIDE
+        ));
+    }
+
+    let luma = width as usize * height as usize;
+    let (half_width, half_height) = (width.div_ceil(2) as usize, height.div_ceil(2) as usize);
+    let chroma = match colorspace {
+        c if c.starts_with("mono") => 0,
+        c if c.starts_with("444") => 2 * luma,
+        c if c.starts_with("422") => 2 * half_width * height as usize,
+        c if c.starts_with("420") => 2 * half_width * half_height,
+        c => return Err(format!("Unsupported Y4M colorspace '{}'", c)),
+    };
+
+    let mut done = false;
+    Ok(Box::new(std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let frame = match line(&mut reader) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return None,
+            Err(e) => {
+                done = true;
+                return Some(Err(e));
+            }
+        };
+        if !frame.as_bytes().starts_with(FRAME_MAGIC) {
+            done = true;
+            return Some(Err(format!("Expected a Y4M frame, got '{}'", frame)));
+        }
+        let mut plane = vec![0; luma];
+        let skipped = reader
+            .read_exact(&mut plane)
+            .and_then(|()| io::copy(&mut reader.by_ref().take(chroma as u64), &mut io::sink()));
+        match skipped {
+            Ok(_) => Some(Ok(GrayImage::from_raw(width, height, plane).unwrap())),
+            // a recording cut short ends here
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => {
+                done = true;
+                Some(Err(e.to_string()))
+            }
+        }
+    })))
+}
+
+/// The next byte, also appended to `jpeg`.
+fn next(reader: &mut impl BufRead, jpeg: &mut Vec<u8>) -> io::Result<u8> {
+    let byte = *reader
+        .fill_buf()?
+        .first()
+        .ok_or(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+    reader.consume(1);
+    jpeg.push(byte);
+    Ok(byte)
+}
+
+/// Reads up to and including the next JPEG start marker, false at the end of
+/// the input.
+fn skip_to_jpeg(reader: &mut impl BufRead) -> io::Result<bool> {
+    let mut matched = 0;
+    while matched < JPEG_START.len() {
+        let Some(&byte) = reader.fill_buf()?.first() else {
+            return Ok(false);
+        };
+        reader.consume(1);
+        matched = if byte == JPEG_START[matched] {
+            matched + 1
+        } else {
+            (byte == 0xff) as usize
+        };
+    }
+    Ok(true)
+}
+
+/// Reads the rest of a JPEG whose start marker is in `jpeg` already, by
+/// stepping over whole segments so the markers of an embedded thumbnail
+/// aren't mistaken for its own.
+fn read_jpeg(reader: &mut impl BufRead, jpeg: &mut Vec<u8>) -> io::Result<()> {
+    // the 0xff of each marker has been read here
+    loop {
+        let mut marker = next(reader, jpeg)?;
+        while marker == 0xff {
+            marker = next(reader, jpeg)?;
+        }
+        match marker {
+            EOI => return Ok(()),
+            0x01 | 0xd0..=0xd7 => {}
+            _ => {
+                let len = u16::from_be_bytes([next(reader, jpeg)?, next(reader, jpeg)?]) as usize;
+                let body = len
+                    .checked_sub(2)
+                    .ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+                if reader.take(body as u64).read_to_end(jpeg)? < body {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                if marker == SOS {
+                    skip_entropy_coded(reader, jpeg)?;
+                    continue;
+                }
+            }
+        }
+        if next(reader, jpeg)? != 0xff {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+    }
+}
+
+/// Entropy-coded data runs to the next marker; 0xff is escaped as ff 00 there,
+/// and restart markers belong to it.
+fn skip_entropy_coded(reader: &mut impl BufRead, jpeg: &mut Vec<u8>) -> io::Result<()> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let Some(end) = buf.iter().position(|&byte| byte == 0xff) else {
+            let len = buf.len();
+            jpeg.extend_from_slice(buf);
+            reader.consume(len);
+            continue;
+        };
+        jpeg.extend_from_slice(&buf[..=end]);
+        reader.consume(end + 1);
+        match reader.fill_buf()?.first() {
+            Some(0x00 | 0xd0..=0xd7) => {
+                next(reader, jpeg)?;
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Every JPEG in the input, each found at a start marker and stepped through
+/// to its end, so this works for raw streams and AVI files alike. `first` is a
+/// JPEG read from it already.
+fn mjpeg_frames<'a>(mut reader: impl BufRead + 'a, mut first: Option<Vec<u8>>) -> Frames<'a> {
+    let mut done = false;
+    Box::new(std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let jpeg = match first.take() {
+            Some(jpeg) => jpeg,
+            None => {
+                let mut jpeg = JPEG_START.to_vec();
+                let read = skip_to_jpeg(&mut reader)
+                    .and_then(|found| found.then(|| read_jpeg(&mut reader, &mut jpeg)).transpose());
+                match read {
+                    Ok(Some(())) => jpeg,
+                    // a recording cut short ends here
+                    Ok(None) => return None,
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+                    Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                        return Some(Err("Failed to read a frame".to_string()))
+                    }
+                    Err(e) => {
+                        done = true;
+                        return Some(Err(e.to_string()));
+                    }
+                }
+            }
+        };
+        Some(
+            image::load_from_memory_with_format(&jpeg, ImageFormat::Jpeg)
+                .map(|frame| frame.into_luma8())
+                .map_err(|e| format!("Failed to decode a frame: {}", e)),
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receive::Decoder;
+    use crate::send::encoder::{Encoder, EncoderOptions};
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{imageops, Luma};
+    use qrcode::QrCode;
+
+    const SIZE: u32 = 400;
+
+    /// Every code of a small transfer, shown twice, as a camera would.
+    fn shots() -> (Vec<u8>, Vec<GrayImage>) {
+        let content = b"Recorded on a phone, decoded on a laptop.".to_vec();
+        let encoder = Encoder::new(
+            "video.txt".to_string(),
+            content.clone(),
+            EncoderOptions::default(),
+        );
+        let mut shots = Vec::new();
+        for payload in encoder.payloads().values() {
+            let code = QrCode::new(payload).unwrap().render::<Luma<u8>>().build();
+            let mut shot = GrayImage::from_pixel(SIZE, SIZE, Luma([255]));
+            imageops::overlay(&mut shot, &code, 20, 20);
+            shots.push(shot.clone());
+            shots.push(shot);
+        }
+        (content, shots)
+    }
+
+    fn receive(frames: Frames) -> Vec<u8> {
+        let mut decoder = Decoder::new();
+        let (mut count, mut new) = (0, 0);
+        for frame in frames {
+            count += 1;
+            new += decoder.scan_gray(&frame.unwrap()).unwrap().len();
+        }
+        // the repeated shots bring nothing new
+        assert_eq!(2 * new, count);
+        decoder.get_finished().unwrap().to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_y4m() {
+        let (content, shots) = shots();
+        let mut video =
+            format!("YUV4MPEG2 W{} H{} F30:1 Ip A1:1 C420jpeg\n", SIZE, SIZE).into_bytes();
+        for shot in &shots {
+            video.extend_from_slice(b"FRAME\n");
+            video.extend_from_slice(shot.as_raw());
+            video.extend(std::iter::repeat_n(128, 2 * (SIZE as usize / 2).pow(2)));
+        }
+        assert_eq!(receive(frames(&video[..]).unwrap().unwrap()), content);
+
+        // a forged header is refused before any frame is allocated
+        let huge = b"YUV4MPEG2 W4000000000 H4000000000 C420jpeg\nFRAME\n";
+        assert!(frames(&huge[..]).is_err());
+        let tall = b"YUV4MPEG2 W100 H8193 Cmono\nFRAME\n";
+        assert!(frames(&tall[..]).is_err());
+    }
+
+    #[test]
+    fn test_mjpeg() {
+        let (content, shots) = shots();
+        let mut video = Vec::new();
+        for shot in &shots {
+            JpegEncoder::new_with_quality(&mut video, 90)
+                .encode_image(shot)
+                .unwrap();
+        }
+        assert_eq!(receive(frames(&video[..]).unwrap().unwrap()), content);
+    }
+
+    /// `jpeg` with an APP1 segment holding a thumbnail, as cameras write.
+    fn with_thumbnail(jpeg: &[u8]) -> Vec<u8> {
+        let mut thumbnail = Vec::new();
+        JpegEncoder::new(&mut thumbnail)
+            .encode_image(&GrayImage::from_pixel(16, 16, Luma([90])))
+            .unwrap();
+        let mut exif = b"Exif\0\0".to_vec();
+        exif.extend(thumbnail);
+        let mut photo = JPEG_START[..2].to_vec();
+        photo.extend([0xff, 0xe1]);
+        photo.extend((exif.len() as u16 + 2).to_be_bytes());
+        photo.extend(exif);
+        photo.extend(&jpeg[2..]);
+        photo
+    }
+
+    #[test]
+    fn test_exif_thumbnail() {
+        let (content, shots) = shots();
+        let mut photo = Vec::new();
+        JpegEncoder::new_with_quality(&mut photo, 90)
+            .encode_image(&shots[0])
+            .unwrap();
+        let photo = with_thumbnail(&photo);
+        assert!(frames(&photo[..]).unwrap().is_none());
+        assert!(image::load_from_memory(&photo).is_ok());
+
+        // frames with thumbnails aren't cut short at the thumbnail's end
+        let mut video = Vec::new();
+        for shot in &shots {
+            let mut jpeg = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpeg, 90)
+                .encode_image(shot)
+                .unwrap();
+            video.extend(with_thumbnail(&jpeg));
+        }
+        assert_eq!(receive(frames(&video[..]).unwrap().unwrap()), content);
+        // markers split between reads
+        let reader = io::BufReader::with_capacity(7, &video[..]);
+        assert_eq!(receive(frames(reader).unwrap().unwrap()), content);
+    }
+}
@@ -0,0 +1,80 @@
+use super::qr::{fits, qr};
+use super::EncoderOptions;
+use crate::calibration::{REPEATS, VERSIONS};
+use crate::protocol::{Frame, Message};
+use indexmap::IndexMap;
+
+const MAX_DATA: usize = 4096;
+
+fn frame(version: usize, index: usize, len: usize, options: &EncoderOptions) -> Vec<u8> {
+    let data = (0..len)
+        .map(|i| (i * 151 + index * 7 + version) as u8)
+        .collect();
+    let message = Message::Calibration {
+        version,
+        index,
+        total: REPEATS,
+        data,
+    };
+    Frame {
+        session: None,
+        message,
+    }
+    .encode(options.encoding)
+    .expect("calibration fields are small")
+}
+
+/// The test codes for `version`, as full as that version allows.
+pub fn test_codes(version: usize, options: &EncoderOptions) -> Vec<Vec<u8>> {
+    let fits = |len| {
+        fits(
+            &frame(version, 0, len, options),
+            version as i16,
+            options.ec_level,
+        )
+    };
+    let (mut low, mut high) = (0, MAX_DATA);
+    while low < high {
+        let mid = (low + high).div_ceil(2);
+        if fits(mid) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    (0..REPEATS)
+        .map(|index| frame(version, index, low, options))
+        .collect()
+}
+
+fn name(version: usize, index: usize) -> String {
+    format!("C{}.{}", version, index + 1)
+}
+
+/// Test codes of increasing QR version, encoded with the given options.
+pub fn payloads(options: &EncoderOptions) -> IndexMap<String, Vec<u8>> {
+    let mut payloads = IndexMap::new();
+    for version in VERSIONS {
+        for (index, payload) in test_codes(version, options).into_iter().enumerate() {
+            payloads.insert(name(version, index), payload);
+        }
+    }
+    payloads
+}
+
+/// Number of test codes over all versions.
+pub fn count() -> usize {
+    VERSIONS.len() * REPEATS
+}
+
+pub fn label(index: usize) -> String {
+    name(VERSIONS[index / REPEATS], index % REPEATS)
+}
+
+/// Test code `index` drawn as an SVG, at the version it tests.
+pub fn svg(index: usize, options: &EncoderOptions) -> String {
+    let version = VERSIONS[index / REPEATS];
+    let options = options.clone().version(Some(version as i16));
+    let payload = test_codes(version, &options).swap_remove(index % REPEATS);
+    qr(&payload, &options)
+}
@@ -1,76 +1,391 @@
-use crate::protocol::{Message, Metadata, Payload};
+use crate::protocol::{FileInfo, Frame, Message, Metadata, MIN_CHUNK_SIZE};
 use crate::utils::hash;
+pub mod calibration;
+mod options;
 mod qr;
-use crate::compress;
+use crate::compress::{Codec, Compressor};
+use crate::crypto;
+use crate::fountain::{self, Distribution};
+use crate::parity::{self, Shard};
+use crate::sign;
 use base64::{prelude::BASE64_STANDARD, Engine as _};
 use indexmap::IndexMap;
-use qr::qr;
+pub use options::{ec_level_name, parse_ec_level, EcLevel, EncoderOptions};
+use qr::{fits, qr, terminal};
+use std::cell::RefCell;
 
+/// Largest piece tried when filling a fixed QR version.
+const MAX_CHUNK_SIZE: usize = 4096;
+/// Frames between repeats of the metadata in endless playback, for receivers
+/// that start scanning late.
+const METADATA_EVERY: usize = 100;
+
+/// Builds an encoder from a file handed over a slice at a time, holding only
+/// the compressed stream.
+pub struct Packer {
+    compressor: Compressor,
+    size: usize,
+    options: EncoderOptions,
+}
+
+impl Packer {
+    pub fn new(options: EncoderOptions) -> Packer {
+        Packer {
+            compressor: options.codec.compressor(),
+            size: 0,
+            options,
+        }
+    }
+
+    pub fn write(&mut self, slice: &[u8]) {
+        self.size += slice.len();
+        self.compressor.write(slice);
+    }
+
+    /// None when compressing didn't make the file smaller, it is then best
+    /// sent as it is with `Codec::None`.
+    pub fn finish(self, file_name: String) -> Option<Encoder> {
+        let data = self.compressor.finish();
+        if data.len() >= self.size && self.options.codec != Codec::None {
+            return None;
+        }
+        let codec = self.options.codec;
+        Some(Encoder::build(
+            file_name,
+            data,
+            self.size,
+            codec,
+            self.options,
+        ))
+    }
+}
+
+#[derive(Clone)]
 pub struct Encoder {
     file_name: String,
     data: Vec<u8>,
+    /// Of the file before compression.
+    size: usize,
+    info: FileInfo,
+    codec: Codec,
+    encrypted: bool,
+    /// Of the data as sent, under the hash algorithm in the options.
+    digest: String,
+    session: u32,
+    options: EncoderOptions,
+    chunk_size: usize,
+    /// Degrees of the fountain symbols for the current chunk size.
+    distribution: Distribution,
+    /// Parity pieces of each group for the current chunk size.
+    parity_totals: Vec<usize>,
+    /// The parity group shown last, whose pieces are shown one after another.
+    parity_group: RefCell<Option<(usize, Vec<Shard>)>>,
 }
 
 impl Encoder {
-    pub fn new(file_name: String, data: Vec<u8>) -> Encoder {
-        Encoder {
+    /// Frames can't describe more than `MAX_FIELD` bytes, with encryption,
+    /// so callers refuse larger files.
+    pub fn new(file_name: String, data: Vec<u8>, options: EncoderOptions) -> Encoder {
+        let data_len = data.len();
+        // JPEGs, ZIPs and the like are sent as they are
+        let compressed = options.codec.compress(&data);
+        let (codec, data) = if compressed.len() < data.len() {
+            (options.codec, compressed)
+        } else {
+            (Codec::None, data)
+        };
+        Encoder::build(file_name, data, data_len, codec, options)
+    }
+
+    /// From data already compressed with `codec`.
+    fn build(
+        file_name: String,
+        mut data: Vec<u8>,
+        data_len: usize,
+        codec: Codec,
+        options: EncoderOptions,
+    ) -> Encoder {
+        if let Some(passphrase) = &options.passphrase {
+            data = crypto::encrypt(data, passphrase);
+        }
+        let mut encoder = Encoder {
             file_name,
-            data: compress::compress(data),
+            // 0 would stand for no session
+            session: u32::from_str_radix(&hash(&data)[..8], 16).unwrap().max(1),
+            size: data_len,
+            info: FileInfo::default(),
+            codec,
+            encrypted: options.passphrase.is_some(),
+            digest: String::new(),
+            data,
+            options: EncoderOptions::default(),
+            chunk_size: 0,
+            distribution: Distribution::new(0),
+            parity_totals: Vec::new(),
+            parity_group: RefCell::new(None),
+        };
+        encoder.set_options(options);
+        encoder
+    }
+
+    /// Sends the modification time, permissions and MIME type along.
+    pub fn set_file_info(&mut self, info: FileInfo) {
+        self.info = info;
+    }
+
+    pub fn options(&self) -> &EncoderOptions {
+        &self.options
+    }
+
+    /// Change how frames are cut and drawn without compressing the file again.
+    /// The passphrase and codec stay the ones given to `new`.
+    pub fn set_options(&mut self, options: EncoderOptions) {
+        if self.digest.is_empty() || options.hash_algorithm != self.options.hash_algorithm {
+            self.digest = options.hash_algorithm.digest(&self.data);
         }
+        self.options = options;
+        self.chunk_size = match self.options.version {
+            Some(version) => self.fill_version(version),
+            None => self.options.chunk_size.max(MIN_CHUNK_SIZE),
+        };
+        let k = self.piece_count();
+        self.distribution = Distribution::new(k);
+        // an empty file has no pieces to protect
+        self.parity_totals = match (self.options.parity, k) {
+            (0, _) | (_, 0) => Vec::new(),
+            (overhead, _) => {
+                let groups = parity::groups(k, overhead);
+                (0..groups)
+                    .map(|group| parity::parity_count((k - group).div_ceil(groups), overhead))
+                    .collect()
+            }
+        };
+        self.parity_group.replace(None);
+    }
+
+    /// Compressed bytes carried by each piece.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
     }
 
-    fn get_chunks(&self) -> Vec<&[u8]> {
-        const SIZE: usize = 100;
-        let mut chunks = Vec::new();
-        let length = self.data.len();
-        for i in (0..length).step_by(SIZE) {
-            chunks.push(&self.data[i..(i + SIZE).min(length)])
+    /// The largest chunk for which both a parity frame, the biggest kind for
+    /// all but tiny files, and a piece with its checksum still fit into the
+    /// given QR version.
+    fn fill_version(&self, version: i16) -> usize {
+        let size = self.data.len();
+        let fits = |chunk: usize| {
+            let parity = Message::Parity {
+                size,
+                groups: size,
+                group: size,
+                total: size,
+                index: size,
+                data: vec![0xa5; chunk],
+            };
+            let piece = Message::piece(size, vec![0xa5; chunk]);
+            [parity, piece]
+                .into_iter()
+                .all(|msg| fits(&self.frame(msg), version, self.options.ec_level))
+        };
+        let (mut low, mut high) = (MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+        while low < high {
+            let mid = (low + high).div_ceil(2);
+            if fits(mid) {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
         }
-        chunks
+        low
     }
 
-    fn get_metadata(&self, length: usize) -> Metadata {
-        Metadata::new(
-            BASE64_STANDARD.encode(self.file_name.as_bytes()),
-            length,
-            hash(&self.data),
-        )
+    fn chunk(&self, index: usize) -> &[u8] {
+        let start = index * self.chunk_size;
+        &self.data[start..(start + self.chunk_size).min(self.data.len())]
+    }
+
+    fn piece_count(&self) -> usize {
+        self.data.len().div_ceil(self.chunk_size)
+    }
+
+    /// Tags every frame of this transfer, derived from the content hash.
+    pub fn session(&self) -> u32 {
+        self.session
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        Metadata {
+            encrypted: self.encrypted,
+            signed: self.options.signing_key.is_some(),
+            hash_algorithm: self.options.hash_algorithm,
+            codec: self.codec,
+            size: Some(self.size),
+            info: self.info.clone(),
+            ..Metadata::new(
+                BASE64_STANDARD.encode(self.file_name.as_bytes()),
+                self.piece_count(),
+                self.digest.clone(),
+            )
+        }
+    }
+
+    /// Endless stream of fountain symbols, any slightly-more-than-k of which
+    /// (together with whatever pieces got through) rebuild the data.
+    pub fn symbols(&self) -> impl Iterator<Item = Message> + '_ {
+        (1..=u32::MAX).map(move |seed| self.symbol(seed))
+    }
+
+    fn symbol(&self, seed: u32) -> Message {
+        let degree = self.distribution.sample(seed);
+        Message::Symbol {
+            seed,
+            degree,
+            size: self.data.len(),
+            data: fountain::encode(
+                self.piece_count(),
+                |i| self.chunk(i),
+                self.chunk_size,
+                seed,
+                degree,
+            ),
+        }
     }
 
-    pub fn get_payload(&self) -> Payload {
-        let chunks = self.get_chunks();
-        let metadata = self.get_metadata(chunks.len());
-        let pieces = chunks
-            .iter()
-            .enumerate()
-            .map(|(counter, data)| (counter + 1, BASE64_STANDARD.encode(data)))
-            .collect();
+    fn frame(&self, message: Message) -> Vec<u8> {
+        Frame::new(self.session(), message)
+            .encode(self.options.encoding)
+            .expect("files too large for the frame fields are refused before encoding")
+    }
 
-        Payload::new(metadata, pieces)
+    fn signed(&self) -> usize {
+        self.options.signing_key.is_some() as usize
     }
 
-    pub fn payloads(self) -> IndexMap<String, String> {
-        let payload = self.get_payload();
+    fn parity_count(&self) -> usize {
+        self.parity_totals.iter().sum()
+    }
 
-        let mut payloads = IndexMap::new();
+    /// Metadata, the signature if a key is set, and pieces, followed by the
+    /// parity pieces and fountain symbols asked for in the options.
+    pub fn frame_count(&self) -> usize {
+        let pieces = self.piece_count();
+        1 + self.signed()
+            + pieces
+            + self.parity_count()
+            + (pieces * self.options.fountain).div_ceil(100)
+    }
 
-        let metadata_msg = Message::Metadata(payload.metadata);
-        payloads.insert("METADATA".to_string(), metadata_msg.to_string());
+    /// Whether playback goes on with new fountain symbols rather than
+    /// starting over, as it does when the options ask for any and there are
+    /// pieces to mix.
+    pub fn is_endless(&self) -> bool {
+        self.options.fountain > 0 && self.piece_count() > 0
+    }
 
-        for (index, data) in payload.pieces {
-            let piece_msg = Message::Piece { index, data };
-            payloads.insert(format!("{}", index), piece_msg.to_string());
+    /// The frame shown at `step` of playback. Past `frame_count`, endless
+    /// playback shows symbols of ever higher seeds, interrupted by the
+    /// metadata and signature every `METADATA_EVERY` frames; otherwise it
+    /// loops.
+    pub fn playback(&self, step: usize) -> usize {
+        let total = self.frame_count();
+        if step < total || total == 0 {
+            return step;
         }
+        if !self.is_endless() {
+            return step % total;
+        }
+        let extra = step - total;
+        let header = 1 + self.signed();
+        match extra % METADATA_EVERY {
+            frame if frame < header => frame,
+            _ => total + extra - (extra / METADATA_EVERY + 1) * header,
+        }
+    }
 
-        payloads
+    /// The message shown as frame `index`, made only when asked for so a
+    /// large file never has all its frames in memory at once.
+    pub fn message(&self, mut index: usize) -> Message {
+        if index == 0 {
+            return Message::Metadata(self.metadata());
+        }
+        index -= 1;
+        if let Some(key) = &self.options.signing_key {
+            if index == 0 {
+                return sign::sign(&self.metadata(), key);
+            }
+            index -= 1;
+        }
+        let pieces = self.piece_count();
+        if index < pieces {
+            return Message::piece(index + 1, self.chunk(index).to_vec());
+        }
+        index -= pieces;
+        let parity_count = self.parity_count();
+        if index < parity_count {
+            return self.parity(index);
+        }
+        index -= parity_count;
+        self.symbol(index as u32 + 1)
     }
 
-    pub fn to_qr(self) -> IndexMap<String, String> {
-        self.payloads()
-            .iter()
-            .map(|(name, payload)| (name.to_string(), qr(payload)))
+    /// The `index`th Reed-Solomon parity piece. Its group is encoded once and
+    /// kept for the group's other pieces, which follow it.
+    fn parity(&self, mut index: usize) -> Message {
+        let mut group = 0;
+        while index >= self.parity_totals[group] {
+            index -= self.parity_totals[group];
+            group += 1;
+        }
+        let mut cached = self.parity_group.borrow_mut();
+        if cached.as_ref().is_none_or(|(cached, _)| *cached != group) {
+            let shards = parity::encode_group(
+                self.piece_count(),
+                |i| self.chunk(i),
+                self.chunk_size,
+                self.options.parity,
+                group,
+            );
+            *cached = Some((group, shards));
+        }
+        let shard = &cached.as_ref().unwrap().1[index];
+        Message::Parity {
+            size: self.data.len(),
+            groups: self.parity_totals.len(),
+            group: shard.group,
+            total: shard.total,
+            index: shard.index,
+            data: shard.data.clone(),
+        }
+    }
+
+    pub fn label(&self, index: usize) -> String {
+        self.message(index).label()
+    }
+
+    /// Frame `index` as it is put into the QR code.
+    pub fn payload(&self, index: usize) -> Vec<u8> {
+        self.frame(self.message(index))
+    }
+
+    /// Every frame at once, for tests and tools working on small files.
+    pub fn payloads(&self) -> IndexMap<String, Vec<u8>> {
+        (0..self.frame_count())
+            .map(|index| {
+                let message = self.message(index);
+                (message.label(), self.frame(message))
+            })
             .collect()
     }
+
+    /// Frame `index` drawn as an SVG.
+    pub fn svg(&self, index: usize) -> String {
+        qr(&self.payload(index), &self.options)
+    }
+
+    /// Frame `index` drawn with Unicode half blocks for a text terminal.
+    pub fn terminal(&self, index: usize) -> String {
+        terminal(&self.payload(index), &self.options)
+    }
 }
 
 #[test]
@@ -88,8 +403,80 @@ fn test_encoder() {
                 116, 104, 40, 114, 39, 37, 49, 39, 41, 41, 41, 34, 40, 80, 97, 116, 104, 40, 114,
                 39, 37, 49, 39, 41, 41, 41, 34, 40, 80, 97, 116, 104, 40, 114, 39, 37, 49, 39, 41,
                 41, 41, 34
-            )
+            ),
+            EncoderOptions::default()
         )
-        .to_qr()
+        .svg(1)
     )
 }
+
+#[test]
+fn test_lazy_frames() {
+    let data = (0..20_000u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+        .collect::<Vec<u8>>();
+    let options = EncoderOptions::new()
+        .codec(Codec::None)
+        .chunk_size(100)
+        .parity(20)
+        .fountain(50)
+        .signing_key(Some(sign::generate_key()));
+    let encoder = Encoder::new("lazy".to_string(), data.clone(), options.clone());
+
+    let payloads = encoder.payloads();
+    assert_eq!(payloads.len(), encoder.frame_count());
+    assert_eq!(encoder.frame_count(), 1 + 1 + 200 + 40 + 100);
+    assert_eq!(encoder.metadata().length, 200);
+    let piece = encoder.message(2 + 199);
+    assert_eq!(piece, Message::piece(200, data[19_900..].to_vec()));
+
+    // parity pieces in the order all of them used to be made in
+    let chunks = data.chunks(100).collect::<Vec<&[u8]>>();
+    for (ordinal, shard) in parity::encode(&chunks, 100, 20).into_iter().enumerate() {
+        match encoder.message(202 + ordinal) {
+            Message::Parity {
+                group, index, data, ..
+            } => assert_eq!((group, index, data), (shard.group, shard.index, shard.data)),
+            other => panic!("expected parity, got {:?}", other),
+        }
+    }
+    assert_eq!(encoder.message(242), encoder.symbols().next().unwrap());
+
+    // endless playback goes on with new symbols, repeating the metadata and
+    // signature for late receivers
+    assert!(encoder.is_endless());
+    let total = encoder.frame_count();
+    assert_eq!(encoder.playback(total - 1), total - 1);
+    assert_eq!(encoder.playback(total), 0);
+    assert_eq!(encoder.playback(total + 1), 1);
+    assert_eq!(encoder.playback(total + 2), total);
+    assert_eq!(encoder.playback(total + 99), total + 97);
+    assert_eq!(encoder.playback(total + 100), 0);
+    assert_eq!(encoder.playback(total + 102), total + 98);
+    assert!(matches!(
+        encoder.message(encoder.playback(total + 102)),
+        Message::Symbol { seed: 199, .. }
+    ));
+    let looping = Encoder::new(
+        "lazy".to_string(),
+        data.clone(),
+        options.clone().fountain(0),
+    );
+    assert_eq!(looping.playback(looping.frame_count() + 3), 3);
+
+    // a file read a slice at a time makes the same frames
+    let mut packer = Packer::new(options.clone());
+    data.chunks(4096).for_each(|slice| packer.write(slice));
+    assert_eq!(
+        packer.finish("lazy".to_string()).unwrap().payloads(),
+        payloads
+    );
+    // compressed data doesn't shrink again, so it is read again to be sent
+    // as it is
+    let compressed = Codec::default().compress(&data);
+    let mut packer = Packer::new(options.codec(Codec::default()));
+    compressed
+        .chunks(4096)
+        .for_each(|slice| packer.write(slice));
+    assert!(packer.finish("lazy".to_string()).is_none());
+}
@@ -0,0 +1,136 @@
+use crate::compress::Codec;
+use crate::protocol::Encoding;
+use crate::sign::SigningKey;
+use crate::utils::HashAlgorithm;
+pub use qrcode::EcLevel;
+
+/// How a file is cut into frames and drawn as QR codes. Denser codes carry
+/// more per frame, more robust ones scan from worse screens and cameras.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncoderOptions {
+    /// Compressed bytes per piece.
+    pub chunk_size: usize,
+    /// Draw every frame at this QR version, sizing the pieces to fill it.
+    pub version: Option<i16>,
+    pub ec_level: EcLevel,
+    /// Pixels per module, or `None` to fit the code into 400x400.
+    pub module_size: Option<u32>,
+    pub quiet_zone: bool,
+    pub encoding: Encoding,
+    /// Fountain symbols to append, in percent of the piece count.
+    pub fountain: usize,
+    /// Reed-Solomon parity pieces to append, in percent of the piece count.
+    pub parity: usize,
+    /// Encrypt the file with this passphrase. Only read by `Encoder::new`.
+    pub passphrase: Option<String>,
+    /// Sign the transfer with this key.
+    pub signing_key: Option<SigningKey>,
+    pub hash_algorithm: HashAlgorithm,
+    /// Compress the file with this codec, unless that doesn't make it any
+    /// smaller. Only read by `Encoder::new`.
+    pub codec: Codec,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        EncoderOptions {
+            chunk_size: 100,
+            version: None,
+            ec_level: EcLevel::M,
+            module_size: None,
+            quiet_zone: true,
+            encoding: Encoding::default(),
+            fountain: 0,
+            parity: 0,
+            passphrase: None,
+            signing_key: None,
+            hash_algorithm: HashAlgorithm::default(),
+            codec: Codec::default(),
+        }
+    }
+}
+
+impl EncoderOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    pub fn version(mut self, version: Option<i16>) -> Self {
+        self.version = version.map(|v| v.clamp(1, 40));
+        self
+    }
+
+    pub fn ec_level(mut self, ec_level: EcLevel) -> Self {
+        self.ec_level = ec_level;
+        self
+    }
+
+    pub fn module_size(mut self, module_size: Option<u32>) -> Self {
+        self.module_size = module_size.filter(|&m| m > 0);
+        self
+    }
+
+    pub fn quiet_zone(mut self, quiet_zone: bool) -> Self {
+        self.quiet_zone = quiet_zone;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn fountain(mut self, fountain: usize) -> Self {
+        self.fountain = fountain;
+        self
+    }
+
+    pub fn parity(mut self, parity: usize) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub fn passphrase(mut self, passphrase: Option<String>) -> Self {
+        self.passphrase = passphrase.filter(|p| !p.is_empty());
+        self
+    }
+
+    pub fn signing_key(mut self, signing_key: Option<SigningKey>) -> Self {
+        self.signing_key = signing_key;
+        self
+    }
+
+    pub fn hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+}
+
+pub fn parse_ec_level(s: &str) -> Option<EcLevel> {
+    match s {
+        "L" | "l" => Some(EcLevel::L),
+        "M" | "m" => Some(EcLevel::M),
+        "Q" | "q" => Some(EcLevel::Q),
+        "H" | "h" => Some(EcLevel::H),
+        _ => None,
+    }
+}
+
+pub fn ec_level_name(ec_level: EcLevel) -> &'static str {
+    match ec_level {
+        EcLevel::L => "L",
+        EcLevel::M => "M",
+        EcLevel::Q => "Q",
+        EcLevel::H => "H",
+    }
+}
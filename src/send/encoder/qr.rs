@@ -1,14 +1,39 @@
-use qrcode::render::svg;
-use qrcode::QrCode;
-
-pub fn qr(content: &str) -> String {
-    let code = QrCode::new(content).unwrap();
-    let image = code
-        .render()
-        .min_dimensions(400, 400)
-        .max_dimensions(400, 400)
+use super::EncoderOptions;
+use qrcode::render::{svg, unicode};
+use qrcode::{EcLevel, QrCode, Version};
+
+pub fn fits(content: &[u8], version: i16, ec_level: EcLevel) -> bool {
+    QrCode::with_version(content, Version::Normal(version), ec_level).is_ok()
+}
+
+fn code(content: &[u8], options: &EncoderOptions) -> QrCode {
+    options
+        .version
+        .and_then(|v| QrCode::with_version(content, Version::Normal(v), options.ec_level).ok())
+        .unwrap_or_else(|| QrCode::with_error_correction_level(content, options.ec_level).unwrap())
+}
+
+pub fn qr(content: &[u8], options: &EncoderOptions) -> String {
+    let code = code(content, options);
+    let mut renderer = code.render::<svg::Color>();
+    renderer
+        .quiet_zone(options.quiet_zone)
         .dark_color(svg::Color("#000000"))
-        .light_color(svg::Color("#ffffff"))
-        .build();
-    image
+        .light_color(svg::Color("#ffffff"));
+    match options.module_size {
+        Some(size) => renderer.module_dimensions(size, size),
+        None => renderer.min_dimensions(400, 400).max_dimensions(400, 400),
+    };
+    renderer.build()
+}
+
+/// Two modules per character cell with Unicode half blocks, light on dark as
+/// most terminals are. The module size does not apply here.
+pub fn terminal(content: &[u8], options: &EncoderOptions) -> String {
+    code(content, options)
+        .render::<unicode::Dense1x2>()
+        .quiet_zone(options.quiet_zone)
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build()
 }
@@ -2,18 +2,21 @@
 
 pub mod encoder;
 
+use crate::bundle;
+use crate::compress::Codec;
+use crate::protocol::{mime_essence, Encoding, FileInfo};
 use crate::utils::log;
-use crate::{QR_INDEX, QR_RES};
+use crate::{CALIBRATING, ENCODER, ENCODER_OPTIONS, QR_COUNT, QR_INDEX};
+use encoder::{ec_level_name, parse_ec_level, EncoderOptions, Packer};
 
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 
 use dioxus::prelude::*;
-use indexmap::IndexMap;
 
 #[derive(PartialEq, Clone, Props, Default)]
 pub struct QrRes {
-    pub payloads: IndexMap<String, String>,
+    pub total: usize,
 }
 
 pub fn QrResPage(props: QrRes) -> Element {
@@ -22,7 +25,7 @@ pub fn QrResPage(props: QrRes) -> Element {
     let mut playback_speed = use_signal(|| 1.0);
     let should_loop = use_signal(|| true);
 
-    let total = props.payloads.len();
+    let total = props.total;
 
     use_hook(|| {
         if total > 0 {
@@ -32,10 +35,12 @@ pub fn QrResPage(props: QrRes) -> Element {
                     let interval_ms = (100.0 / speed) as u32;
                     gloo_timers::future::TimeoutFuture::new(interval_ms).await;
 
-                    if *is_playing.read() {
+                    // the settings panel may have re-encoded into a different count
+                    let total = *QR_COUNT.read();
+                    if *is_playing.read() && total > 0 {
                         let current = *QR_INDEX.read();
                         let next = current + 1;
-                        if next >= total {
+                        if next >= total && !is_endless() {
                             if *should_loop.read() {
                                 *QR_INDEX.write() = 0;
                             } else {
@@ -50,14 +55,30 @@ pub fn QrResPage(props: QrRes) -> Element {
         }
     });
 
-    if props.payloads.is_empty() {
+    if total == 0 {
         return rsx! {
             div {}
         };
     }
 
-    let current_index = *qr_index.read() % total;
-    let (_name, svg) = props.payloads.get_index(current_index).unwrap();
+    let step = *qr_index.read();
+    let calibrating = *CALIBRATING.read();
+    // only the frame on screen is ever drawn
+    let (current_index, svg) = if calibrating {
+        let index = step % total;
+        (
+            index,
+            encoder::calibration::svg(index, &ENCODER_OPTIONS.read()),
+        )
+    } else {
+        match ENCODER.read().as_ref() {
+            Some(encoder) => {
+                let index = encoder.playback(step);
+                (index, encoder.svg(index))
+            }
+            None => return rsx! { div {} },
+        }
+    };
 
     let play_pause_text = if *is_playing.read() {
         "⏸️"
@@ -66,11 +87,18 @@ pub fn QrResPage(props: QrRes) -> Element {
     };
     let speed_text = format!("{}x", *playback_speed.read());
 
-    let title = if current_index == 0 {
+    let title = if calibrating {
+        let name = encoder::calibration::label(current_index);
+        let (version, index) = name[1..].split_once('.').unwrap_or_default();
+        format!("QR version {}, test code {}", version, index)
+    } else if current_index == 0 {
         "* Scan this METADATA before playing".to_string()
+    } else if current_index >= total {
+        format!("{}, fountain codes until stopped", current_index)
     } else {
         format!("{} / {}", current_index, total - 1)
     };
+    let position = step.min(total - 1);
 
     rsx! {
         div { style: "display: flex; flex-direction: column; align-items: center; justify-content: center; height: 100vh;",
@@ -78,8 +106,10 @@ pub fn QrResPage(props: QrRes) -> Element {
                 button {
                     style: "font-size: 18px; padding: 5px 15px; cursor: pointer;",
                     onclick: move |_| {
-                        *QR_RES.write() = IndexMap::new();
+                        *QR_COUNT.write() = 0;
                         *QR_INDEX.write() = 0;
+                        *ENCODER.write() = None;
+                        *CALIBRATING.write() = false;
                     },
                     "← Back"
                 }
@@ -93,7 +123,7 @@ pub fn QrResPage(props: QrRes) -> Element {
                     style: "width: 300px;",
                     min: "0",
                     max: "{total - 1}",
-                    value: "{current_index}",
+                    value: "{position}",
                     oninput: move |evt| {
                         if let Ok(val) = evt.value().parse::<usize>() {
                             *QR_INDEX.write() = val;
@@ -128,7 +158,10 @@ pub fn QrResPage(props: QrRes) -> Element {
                 button {
                     style: "font-size: 24px; padding: 5px 15px; cursor: pointer;",
                     onclick: move |_| {
-                        let idx = (*QR_INDEX.read() + 1) % total;
+                        let mut idx = *QR_INDEX.read() + 1;
+                        if !is_endless() {
+                            idx %= total;
+                        }
                         *QR_INDEX.write() = idx;
                     },
                     "⏩"
@@ -150,21 +183,250 @@ pub fn QrResPage(props: QrRes) -> Element {
                     option { value: "10x", "10x" }
                 }
             }
+            if calibrating {
+                CalibrationPanel {}
+            }
+            EncoderSettings {}
+        }
+    }
+}
+
+/// Whether the transfer on screen plays on past its last frame, see
+/// `Encoder::playback`.
+fn is_endless() -> bool {
+    !*CALIBRATING.read()
+        && ENCODER
+            .read()
+            .as_ref()
+            .is_some_and(|encoder| encoder.is_endless())
+}
+
+/// Re-encode the current file with new options, keeping the compressed data.
+fn apply_options(options: EncoderOptions) {
+    *ENCODER_OPTIONS.write() = options.clone();
+    let total = match ENCODER.write().as_mut() {
+        Some(encoder) => {
+            encoder.set_options(options);
+            encoder.frame_count()
+        }
+        None if *CALIBRATING.read() => encoder::calibration::count(),
+        None => return,
+    };
+    *QR_COUNT.write() = total;
+    if *QR_INDEX.read() >= total {
+        *QR_INDEX.write() = 0;
+    }
+}
+
+/// Cycle test codes of increasing QR version for the receiver to report on.
+pub fn calibrate() {
+    *CALIBRATING.write() = true;
+    *QR_INDEX.write() = 0;
+    *QR_COUNT.write() = encoder::calibration::count();
+}
+
+fn CalibrationPanel() -> Element {
+    let mut reported = use_signal(String::new);
+
+    rsx! {
+        div { style: "margin-top: 20px; width: 400px;",
+            p { "Play the codes and enter the version the receiver shows." }
+            div { class: "input-group",
+                input {
+                    class: "form-control",
+                    r#type: "number",
+                    min: "1",
+                    max: "40",
+                    placeholder: "QR version",
+                    value: "{reported}",
+                    oninput: move |evt| reported.set(evt.value()),
+                }
+                button {
+                    class: "btn btn-primary",
+                    onclick: move |_| {
+                        let Ok(version) = reported.read().trim().parse::<i16>() else {
+                            return;
+                        };
+                        let options = ENCODER_OPTIONS.read().clone().version(Some(version));
+                        *ENCODER_OPTIONS.write() = options;
+                        *CALIBRATING.write() = false;
+                        *QR_INDEX.write() = 0;
+                        *QR_COUNT.write() = 0;
+                    },
+                    "Use this version"
+                }
+            }
+        }
+    }
+}
+
+fn EncoderSettings() -> Element {
+    let options = ENCODER_OPTIONS.read().clone();
+    let chunk_size = ENCODER
+        .read()
+        .as_ref()
+        .map(|encoder| encoder.chunk_size())
+        .unwrap_or(options.chunk_size);
+    let version = options.version.map(|v| v.to_string()).unwrap_or_default();
+    let module_size = options
+        .module_size
+        .map(|m| m.to_string())
+        .unwrap_or_default();
+    let encoding = match options.encoding {
+        Encoding::Text => "text",
+        Encoding::Base45 => "base45",
+        Encoding::Binary => "binary",
+    };
+
+    let with = move |update: fn(EncoderOptions, String) -> EncoderOptions| {
+        move |evt: Event<FormData>| {
+            let options = ENCODER_OPTIONS.read().clone();
+            apply_options(update(options, evt.value()));
+        }
+    };
+
+    rsx! {
+        details { style: "margin-top: 20px; width: 400px;",
+            summary { "Settings" }
+            div { class: "input-group input-group-sm mb-1",
+                label { class: "input-group-text", "Chunk bytes" }
+                input {
+                    class: "form-control",
+                    r#type: "number",
+                    min: "16",
+                    disabled: options.version.is_some(),
+                    value: "{chunk_size}",
+                    onchange: with(|o, v| match v.parse() {
+                        Ok(size) => o.chunk_size(size),
+                        Err(_) => o,
+                    }),
+                }
+            }
+            div { class: "input-group input-group-sm mb-1",
+                label { class: "input-group-text", "QR version" }
+                input {
+                    class: "form-control",
+                    r#type: "number",
+                    min: "1",
+                    max: "40",
+                    placeholder: "auto",
+                    value: "{version}",
+                    onchange: with(|o, v| o.version(v.parse().ok())),
+                }
+            }
+            div { class: "input-group input-group-sm mb-1",
+                label { class: "input-group-text", "Error correction" }
+                select {
+                    class: "form-select",
+                    value: ec_level_name(options.ec_level),
+                    onchange: with(|o, v| match parse_ec_level(&v) {
+                        Some(level) => o.ec_level(level),
+                        None => o,
+                    }),
+                    option { value: "L", "L (7%)" }
+                    option { value: "M", "M (15%)" }
+                    option { value: "Q", "Q (25%)" }
+                    option { value: "H", "H (30%)" }
+                }
+            }
+            div { class: "input-group input-group-sm mb-1",
+                label { class: "input-group-text", "Module size" }
+                input {
+                    class: "form-control",
+                    r#type: "number",
+                    min: "1",
+                    placeholder: "fit",
+                    value: "{module_size}",
+                    onchange: with(|o, v| o.module_size(v.parse().ok())),
+                }
+            }
+            div { class: "input-group input-group-sm mb-1",
+                label { class: "input-group-text", "Encoding" }
+                select {
+                    class: "form-select",
+                    value: encoding,
+                    onchange: with(|o, v| match v.parse() {
+                        Ok(encoding) => o.encoding(encoding),
+                        Err(_) => o,
+                    }),
+                    option { value: "binary", "Binary" }
+                    option { value: "base45", "Base45" }
+                    option { value: "text", "Base64 text" }
+                }
+            }
+            div { class: "input-group input-group-sm mb-1",
+                label { class: "input-group-text", "Hash" }
+                select {
+                    class: "form-select",
+                    value: options.hash_algorithm.name(),
+                    onchange: with(|o, v| match v.parse() {
+                        Ok(algorithm) => o.hash_algorithm(algorithm),
+                        Err(_) => o,
+                    }),
+                    option { value: "sha256", "SHA-256" }
+                    option { value: "blake3", "BLAKE3" }
+                    option { value: "sha1", "SHA-1 (older receivers)" }
+                }
+            }
+            div { class: "input-group input-group-sm mb-1",
+                label { class: "input-group-text", "Fountain codes" }
+                select {
+                    class: "form-select",
+                    value: "{options.fountain}",
+                    onchange: with(|o, v| o.fountain(v.parse().unwrap_or(0))),
+                    option { value: "0", "None" }
+                    option { value: "50", "50%" }
+                    option { value: "100", "100%" }
+                }
+            }
+            div { class: "input-group input-group-sm mb-1",
+                label { class: "input-group-text", "Parity codes" }
+                select {
+                    class: "form-select",
+                    value: "{options.parity}",
+                    onchange: with(|o, v| o.parity(v.parse().unwrap_or(0))),
+                    option { value: "0", "None" }
+                    option { value: "10", "10%" }
+                    option { value: "20", "20%" }
+                    option { value: "50", "50%" }
+                }
+            }
+            div { class: "form-check",
+                input {
+                    class: "form-check-input",
+                    id: "quiet-zone",
+                    r#type: "checkbox",
+                    checked: options.quiet_zone,
+                    onchange: move |evt: Event<FormData>| {
+                        let options = ENCODER_OPTIONS.read().clone();
+                        apply_options(options.quiet_zone(evt.checked()));
+                    },
+                }
+                label { class: "form-check-label", r#for: "quiet-zone", "Quiet zone" }
+            }
         }
     }
 }
 
-fn send(file_name: String, data: Vec<u8>) {
+fn send(file_name: String, data: Vec<u8>, info: FileInfo) {
     log(&format!("Sending file: {}", file_name));
-    let qr = encoder::Encoder::new(file_name, data).to_qr();
-    log("setting QR_RES");
+    let options = ENCODER_OPTIONS.read().clone();
+    start(encoder::Encoder::new(file_name, data, options), info);
+}
 
+fn start(mut encoder: encoder::Encoder, info: FileInfo) {
+    encoder.set_file_info(info);
+    let total = encoder.frame_count();
+    log(&format!("{} frames", total));
+
+    *ENCODER.write() = Some(encoder);
     *QR_INDEX.write() = 0;
-    *QR_RES.write() = qr;
-    log("QR_RES set");
+    *QR_COUNT.write() = total;
 }
 
-pub async fn read_file_content() {
+/// Reads the files picked in the `input_id` file input. Several files, or a
+/// folder, are sent together as a bundle.
+pub async fn read_file_content(input_id: &str) {
     let window = web_sys::window().expect("no global `window` exists");
     let document = window.document().expect("should have a document on window");
 
@@ -174,51 +436,147 @@ pub async fn read_file_content() {
     progress_div.set_inner_html("Processing...");
 
     let filelist = document
-        .get_element_by_id("file-selector")
-        .expect("should have a file-selector element")
+        .get_element_by_id(input_id)
+        .expect("should have a file input element")
         .dyn_into::<web_sys::HtmlInputElement>()
         .unwrap()
         .files()
         .expect("Failed to get filelist from File Input!");
+    let files = (0..filelist.length())
+        .filter_map(|i| filelist.get(i))
+        .collect::<Vec<web_sys::File>>();
+    if files.is_empty() {
+        progress_div.set_inner_html("");
+        return;
+    }
 
-    let file = filelist.get(0).expect("Failed to get File from filelist!");
-    let file_name = file.name();
-    log(&file_name);
-
-    const MAX_FILE_SIZE_MB: u64 = 20;
-    const MAX_FILE_SIZE: u64 = MAX_FILE_SIZE_MB * 1024 * 1024;
-    let file_size = file.size() as u64;
+    // set when a folder was picked
+    let path = |file: &web_sys::File| {
+        js_sys::Reflect::get(file, &"webkitRelativePath".into())
+            .ok()
+            .and_then(|path| path.as_string())
+            .filter(|path| !path.is_empty())
+            .unwrap_or_else(|| file.name())
+    };
+    // a single file is compressed as it is read, so one copy of what is sent
+    // is held, the file itself when it does not compress; bundles are packed
+    // whole first
+    const MAX_FILE_SIZE_MB: u64 = 256;
+    const MAX_BUNDLE_SIZE_MB: u64 = 20;
+    let file_size = files.iter().map(|file| file.size() as u64).sum::<u64>();
+    let single = files.len() == 1 && !path(&files[0]).contains('/');
+    let max_size_mb = if single {
+        MAX_FILE_SIZE_MB
+    } else {
+        MAX_BUNDLE_SIZE_MB
+    };
+    let max_size = max_size_mb * 1024 * 1024;
 
-    if file_size > MAX_FILE_SIZE {
+    if file_size > max_size {
         progress_div.set_inner_html(&format!(
-            "<span style='color: var(--text-error);'>File too large: {:.2}MB. Maximum size is {MAX_FILE_SIZE_MB}MB.</span>",
+            "<span style='color: var(--text-error);'>File too large: {:.2}MB. Maximum size is {max_size_mb}MB.</span>",
             file_size as f64 / (1024.0 * 1024.0)
         ));
         return;
     }
 
+    if single {
+        let file = &files[0];
+        log(&file.name());
+        let info = FileInfo {
+            mtime: Some((file.last_modified() / 1000.0) as u64),
+            mode: None,
+            mime: mime_essence(&file.type_()),
+        };
+        let mut options = ENCODER_OPTIONS.read().clone();
+        // Brotli at its best takes minutes on files this large
+        if file_size > MAX_BUNDLE_SIZE_MB * 1024 * 1024
+            && matches!(options.codec, Codec::Brotli(11))
+        {
+            options = options.codec(Codec::Zstd);
+        }
+        let mut packer = Packer::new(options.clone());
+        read_slices(file, &progress_div, |slice| packer.write(slice)).await;
+        let encoder = match packer.finish(file.name()) {
+            Some(encoder) => encoder,
+            None => {
+                // JPEGs, ZIPs and the like are sent as they are
+                let mut packer = Packer::new(options.codec(Codec::None));
+                read_slices(file, &progress_div, |slice| packer.write(slice)).await;
+                packer.finish(file.name()).unwrap()
+            }
+        };
+        start(encoder, info);
+        return;
+    }
+
+    let mut contents = Vec::new();
+    for file in &files {
+        log(&path(file));
+        contents.push((path(file), read_file(file, &progress_div).await));
+    }
+    let paths = contents
+        .iter()
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<String>>();
+    send(
+        bundle::name(&paths),
+        bundle::pack(&contents),
+        FileInfo::default(),
+    );
+}
+
+/// Bytes read per `Blob.slice`.
+const READ_CHUNK: f64 = (4 << 20) as f64;
+
+async fn read_file(file: &web_sys::File, progress_div: &web_sys::Element) -> Vec<u8> {
+    let mut data = Vec::with_capacity(file.size() as usize);
+    read_slices(file, progress_div, |slice| data.extend_from_slice(slice)).await;
+    data
+}
+
+/// Hands the file to `write` one `Blob.slice` at a time.
+async fn read_slices(
+    file: &web_sys::File,
+    progress_div: &web_sys::Element,
+    mut write: impl FnMut(&[u8]),
+) {
+    let size = file.size();
+    let mut buffer = Vec::new();
+    let mut start = 0.0;
+    while start < size {
+        let end = (start + READ_CHUNK).min(size);
+        progress_div.set_inner_html(&format!(
+            "Reading {}: {:.0}%",
+            file.name(),
+            start / size * 100.0
+        ));
+        let blob = file
+            .slice_with_f64_and_f64(start, end)
+            .expect("blob not sliceable");
+        let array = read_blob(&blob).await;
+        buffer.resize(array.length() as usize, 0);
+        array.copy_to(&mut buffer);
+        write(&buffer);
+        start = end;
+    }
+    progress_div.set_inner_html("Processing...");
+}
+
+async fn read_blob(blob: &web_sys::Blob) -> js_sys::Uint8Array {
     let file_reader = web_sys::FileReader::new().unwrap();
 
     let fr_c = file_reader.clone();
 
     let (rx, tx) = futures::channel::oneshot::channel();
-    let onloadend_cb: Closure<dyn FnMut()> = Closure::new({
-        let mut rx = Some(rx);
-        move || {
-            let array = js_sys::Uint8Array::new(&fr_c.result().unwrap());
-            let _ = rx
-                .take()
-                .expect("multiple files read without refreshing the channel")
-                .send(array.to_vec());
-        }
+    let onloadend_cb = Closure::once(move || {
+        let _ = rx.send(js_sys::Uint8Array::new(&fr_c.result().unwrap()));
     });
 
     file_reader.set_onloadend(Some(onloadend_cb.as_ref().unchecked_ref()));
-    onloadend_cb.forget();
     file_reader
-        .read_as_array_buffer(&file)
+        .read_as_array_buffer(blob)
         .expect("blob not readable");
 
-    let array = tx.await.unwrap();
-    send(file_name.clone(), array);
+    tx.await.unwrap()
 }
@@ -0,0 +1,169 @@
+//! Optional Ed25519 signatures, so a receiver can tell a transfer came from a
+//! sender it trusts. The signature covers the metadata, which names the hash of
+//! the content, and travels in a frame of its own.
+
+use crate::protocol::{Message, Metadata};
+use ed25519_dalek::{Signature, Signer, Verifier};
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+use std::fmt;
+
+/// Keeps signatures of transfers from being valid for anything else.
+const CONTEXT: &str = "qrtransfer signature 1\n";
+
+fn signed_bytes(metadata: &Metadata) -> Vec<u8> {
+    format!("{}{}", CONTEXT, metadata).into_bytes()
+}
+
+pub fn sign(metadata: &Metadata, key: &SigningKey) -> Message {
+    Message::Signature {
+        key: key.verifying_key().to_bytes().to_vec(),
+        signature: key.sign(&signed_bytes(metadata)).to_bytes().to_vec(),
+    }
+}
+
+pub fn generate_key() -> SigningKey {
+    let mut seed = [0; 32];
+    getrandom::getrandom(&mut seed).expect("No source of randomness.");
+    SigningKey::from_bytes(&seed)
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex<const N: usize>(hex: &str) -> Result<[u8; N], String> {
+    let hex = hex.trim();
+    if hex.len() != 2 * N || !hex.is_ascii() {
+        return Err(format!("Expected {} hex digits", 2 * N));
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+            .map_err(|e| format!("Failed to parse key: {}", e))?;
+    }
+    Ok(bytes)
+}
+
+/// A public key as printed by `qrtransfer keygen`.
+pub fn parse_key(hex: &str) -> Result<VerifyingKey, String> {
+    VerifyingKey::from_bytes(&from_hex(hex)?).map_err(|e| format!("Invalid public key: {}", e))
+}
+
+pub fn parse_signing_key(hex: &str) -> Result<SigningKey, String> {
+    Ok(SigningKey::from_bytes(&from_hex(hex)?))
+}
+
+/// The first 8 bytes of a public key, enough to tell keys apart by eye.
+pub fn fingerprint(key: &VerifyingKey) -> String {
+    to_hex(&key.as_bytes()[..8])
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verification {
+    Unsigned,
+    /// The signature does not match the transfer.
+    Invalid,
+    /// A valid signature by a key not on the trusted list.
+    Untrusted(String),
+    Trusted(String),
+}
+
+impl Verification {
+    pub fn is_trusted(&self) -> bool {
+        matches!(self, Verification::Trusted(_))
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verification::Unsigned => write!(f, "Not signed."),
+            Verification::Invalid => write!(f, "Invalid signature!"),
+            Verification::Untrusted(key) => write!(f, "Signed by untrusted key {}.", key),
+            Verification::Trusted(key) => write!(f, "Signed by trusted key {}.", key),
+        }
+    }
+}
+
+/// Checks one signature frame against the metadata it came with.
+pub fn verify(
+    metadata: &Metadata,
+    key: &[u8],
+    signature: &[u8],
+    trusted: &[VerifyingKey],
+) -> Verification {
+    let key = <[u8; 32]>::try_from(key)
+        .ok()
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok());
+    let (Some(key), Ok(signature)) = (key, Signature::from_slice(signature)) else {
+        return Verification::Invalid;
+    };
+    if key.verify(&signed_bytes(metadata), &signature).is_err() {
+        return Verification::Invalid;
+    }
+    if trusted.contains(&key) {
+        Verification::Trusted(fingerprint(&key))
+    } else {
+        Verification::Untrusted(fingerprint(&key))
+    }
+}
+
+#[test]
+fn test_sign() {
+    let key = generate_key();
+    let public = parse_key(&to_hex(key.verifying_key().as_bytes())).unwrap();
+    let metadata = Metadata {
+        signed: true,
+        ..Metadata::new("bmFtZQ==".to_string(), 3, "abc123".to_string())
+    };
+    let Message::Signature {
+        key: sender,
+        signature,
+    } = sign(&metadata, &key)
+    else {
+        unreachable!()
+    };
+
+    let trusted = [public];
+    assert!(verify(&metadata, &sender, &signature, &trusted).is_trusted());
+    assert_eq!(
+        verify(&metadata, &sender, &signature, &[]),
+        Verification::Untrusted(fingerprint(&public))
+    );
+
+    let other = Metadata {
+        hash: "abc124".to_string(),
+        ..metadata.clone()
+    };
+    assert_eq!(
+        verify(&other, &sender, &signature, &trusted),
+        Verification::Invalid
+    );
+    assert_eq!(
+        verify(&metadata, &sender, &signature[1..], &trusted),
+        Verification::Invalid
+    );
+
+    // options this receiver doesn't know are still covered
+    let newer = Metadata {
+        extra: {
+            let mut extra = crate::protocol::Options::default();
+            extra.set("note", "from a newer sender");
+            extra
+        },
+        ..metadata.clone()
+    };
+    let Message::Signature { signature, .. } = sign(&newer, &key) else {
+        unreachable!()
+    };
+    let received = newer.to_string().parse::<Metadata>().unwrap();
+    assert!(verify(&received, &sender, &signature, &trusted).is_trusted());
+    assert_eq!(
+        verify(&metadata, &sender, &signature, &trusted),
+        Verification::Invalid
+    );
+
+    let restored = parse_signing_key(&to_hex(&key.to_bytes())).unwrap();
+    assert_eq!(restored.verifying_key(), public);
+    assert!(parse_key("00").is_err());
+}
@@ -1,4 +1,6 @@
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::str::FromStr;
 
 pub fn set_panic_hook() {
     console_error_panic_hook::set_once();
@@ -9,15 +11,94 @@ pub fn log(msg: &str) {
     web_sys::console::log_1(&wasm_bindgen::JsValue::from_str(msg));
 }
 
+/// Natively the decoder's chatter stays out of the CLI's output, what the user
+/// needs is in its progress; tests still show it on stderr.
 #[cfg(not(target_arch = "wasm32"))]
 pub fn log(msg: &str) {
-    println!("{:?}", msg);
+    if cfg!(test) {
+        eprintln!("{}", msg);
+    }
 }
 
+/// Milliseconds since the epoch; `Instant` is unavailable in the browser.
+#[cfg(target_arch = "wasm32")]
+pub fn now() -> f64 {
+    js_sys::Date::now()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+}
+
+/// How content hashes are computed, named in the metadata. Metadata naming
+/// none is from an older sender and means SHA-1.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    Sha1,
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// The lowercase hex digest of `data`.
+    pub fn digest(&self, data: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Sha1 => format!("{:x}", Sha1::digest(data)),
+            HashAlgorithm::Sha256 => format!("{:x}", Sha256::digest(data)),
+            HashAlgorithm::Blake3 => blake3::hash(data).to_hex().to_string(),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            _ => Err(format!("Unknown hash '{}'", s)),
+        }
+    }
+}
+
+/// The hex digest with the default algorithm.
 pub fn hash(data: &[u8]) -> String {
-    format!("{:x}", {
-        let mut hasher = Sha1::new();
-        hasher.update(data);
-        hasher.finalize()
-    })
+    HashAlgorithm::default().digest(data)
+}
+
+#[test]
+fn test_hash_algorithms() {
+    assert_eq!(
+        HashAlgorithm::Sha1.digest(b"abc"),
+        "a9993e364706816aba3e25717850c26c9cd0d89d"
+    );
+    assert_eq!(
+        HashAlgorithm::Sha256.digest(b"abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+        HashAlgorithm::Blake3.digest(b"abc"),
+        "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+    );
+    for algorithm in [
+        HashAlgorithm::Sha1,
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Blake3,
+    ] {
+        assert_eq!(algorithm.name().parse(), Ok(algorithm));
+    }
 }